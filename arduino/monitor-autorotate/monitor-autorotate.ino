@@ -14,6 +14,7 @@
 #define DC3 'g'
 #define DC4 'h'//*/
 
+#define SOH 0x01
 #define SYN 0x16
 #define ACK 0x06
 #define NAK 0x15
@@ -22,6 +23,22 @@
 #define DC2 0x12
 #define DC3 0x13
 #define DC4 0x14//*/
+#define SO  0x0E
+#define SI  0x0F
+#define DLE 0x10
+#define RS  0x1E
+
+// framed protocol (v2): STX, version, seq, length, payload, crc16 (big endian, over everything after STX)
+#define STX 0x02
+#define PROTOCOL_VERSION 2
+#define MAX_PAYLOAD 64
+
+// answered to SOH (HELLO): SOH, major, minor, patch, command count, commands, name
+#define FIRMWARE_NAME "monitor-autorotate"
+#define FIRMWARE_MAJOR 1
+#define FIRMWARE_MINOR 4
+#define FIRMWARE_PATCH 0
+const byte COMMANDS[] = {SYN, ENQ, DC1, DC2, DC3, SOH, SO, SI, DLE, RS};
 
 #define LANDSCAPE 0
 #define PORTRAIT 1,
@@ -34,6 +51,25 @@ int   current_ori     =  0;
 int   angle_threshold = 65;
 int   angle_limit     = 65+90;
 
+// tuning (SO/SI): threshold, hysteresis, debounce (big endian)
+int           hysteresis  = 5;     // after rotating, the angle has to come back within threshold-hysteresis before it can rotate again
+unsigned long debounce_ms = 0;     // how long the angle has to stay past the threshold
+bool          armed       = true;  // false after rotating, until the angle came back
+bool          past        = false; // whether the angle is past the threshold
+unsigned long past_since  = 0;
+
+// telemetry (DLE): a frame with seq 0 every telemetry_ms, DLE, millis (u32), deviation, offset (floats), all big endian
+unsigned int  telemetry_ms = 0; // 0 while it's off
+unsigned long last_sample  = 0;
+
+// raw readings (RS), for when the host decides: like telemetry, but RS, millis, x, y, z (floats, in g)
+unsigned int  raw_ms       = 0; // 0 while it's off
+unsigned long last_reading = 0;
+
+bool framed  = false; // whether the last command came in a frame, replies and pushes go out the same way
+byte seq_in  = 0;
+byte seq_out = 0;
+
 void setup() {
   Serial.begin(9600);
   pinMode(LED_RED,   OUTPUT);
@@ -49,59 +85,70 @@ void setup() {
 
 float x, y, z, angle;
 void loop() {
-  if (running==true && IMU.accelerationAvailable()) {
+  if (imu_needed() && IMU.accelerationAvailable()) {
     IMU.readAcceleration(x, y, z);
-    angle = deviation(x,y)-offset;
+    float dev = deviation(x,y);
+    angle = wrap(dev-offset);
 
     //Serial.print("\n");
     //Serial.println(angle);
-    if (angle > angle_threshold) {
-      if (angle >= angle_limit) {
-        current_ori = (current_ori+2)%4;
-        offset += 180;
-      } else {
-        if (current_ori == LANDSCAPE) {current_ori = PORTRAIT_FLIPPED;}
-        else {current_ori -= 1;}
-        offset += 90;
-      }
-      serial_changeori(DC1+current_ori);
-    } else if (-angle > angle_threshold) {
-      if (angle >= angle_limit) {
-        current_ori = (current_ori+2)%4;
-        offset -= 180;
-      } else {
-        current_ori = (current_ori+1)%4;
-        serial_changeori(DC1+current_ori);
-        offset -= 90;
-      }
+    if (telemetry_ms > 0 && millis() - last_sample >= telemetry_ms) {
+      last_sample = millis();
+      send_sample(dev);
+    }
+    if (raw_ms > 0 && millis() - last_reading >= raw_ms) {
+      last_reading = millis();
+      send_reading(x, y, z);
     }
+    if (running==true) {update_orientation();}
   }
 
   if (Serial.available() > 0) {
     //Serial.write(ACK);
     //int r = Serial.read();
     //Serial.write(r);
-    switch (Serial.read()) {
+    int command = Serial.read();
+    byte payload[MAX_PAYLOAD];
+    int len = 1;
+    framed = false;
+    if (command == STX) {
+      len = read_frame(payload);
+      if (len > 0) {
+        framed = true;
+        command = payload[0];
+      } else {command = -1;}
+    }
+
+    switch (command) {
       case DC1:
-        if (running==true) {Serial.write(NAK);} else {
+        if (running==true) {reply(NAK);} else {
           digitalWrite(PIN_LED, HIGH);
-          Serial.write(ENQ);
+          reply(ENQ);
           
           digitalWrite(LED_RED,   HIGH);
           digitalWrite(LED_BLUE,   LOW);
           digitalWrite(LED_GREEN, HIGH);
           
-          while (Serial.available() < 2) {delay(1);}
-          current_ori = Serial.read();
-          angle_threshold = Serial.read();
+          if (framed) {
+            byte settings[MAX_PAYLOAD];
+            while (Serial.read() != STX || read_frame(settings) != 2) {delay(1);}
+            current_ori = settings[0];
+            angle_threshold = settings[1];
+          } else {
+            while (Serial.available() < 2) {delay(1);}
+            current_ori = Serial.read();
+            angle_threshold = Serial.read();
+          }
           angle_limit = angle_threshold+90;
           //Serial.read(); Serial.read();
 
+          armed   = true;
+          past    = false;
+          if (!imu_needed()) {IMU.begin();}
           running = true;
-          IMU.begin();
           
           digitalWrite(LED_BLUE,  HIGH);
-          Serial.write(ACK);
+          reply(ACK);
         }
         
         break;
@@ -109,24 +156,49 @@ void loop() {
         if (running==true) {
           digitalWrite(PIN_LED, LOW);
           running = false;
-          IMU.end();
-          Serial.write(ACK);
-        } else {Serial.write(NAK);}
+          if (!imu_needed()) {IMU.end();}
+          reply(ACK);
+        } else {reply(NAK);}
         break;
       case DC3:
-        Serial.write(ACK);
-        if (running==true) {calibrate_IMU();} else {
+        reply(ACK);
+        if (imu_needed()) {calibrate_IMU();} else {
           IMU.begin();
           calibrate_IMU();
           IMU.end();
         }
         break;
       case ENQ:
-        if (running==true) {Serial.write(ACK);}
-        else         {Serial.write(NAK);}
+        if (running==true) {reply(ACK);}
+        else         {reply(NAK);}
         break;
       case SYN:
-        Serial.write(ACK);
+        reply(ACK);
+        break;
+      case SOH:
+        if (framed) {hello();} //doesn't fit in a single byte
+        break;
+      case SO:
+        if (framed) {send_tuning();}
+        break;
+      case SI:
+        if (!framed) {break;}
+        if (len == 5 && set_tuning(payload[1], payload[2], (payload[3] << 8) | payload[4])) {reply(ACK);}
+        else {reply(NAK);}
+        break;
+      case DLE:
+        if (!framed) {break;}
+        if (len == 3) {
+          set_telemetry((payload[1] << 8) | payload[2]);
+          reply(ACK);
+        } else {reply(NAK);}
+        break;
+      case RS:
+        if (!framed) {break;}
+        if (len == 3) {
+          set_raw((payload[1] << 8) | payload[2]);
+          reply(ACK);
+        } else {reply(NAK);}
         break;
     }
     //receivedChar = Serial.read();    
@@ -145,16 +217,208 @@ void loop() {
   }*/
 }
 
+void update_orientation() {
+  if (!armed) {
+    if (fabs(angle) < angle_threshold-hysteresis) {armed = true;}
+  } else if (fabs(angle) > angle_threshold) {
+    if (!past) {
+      past = true;
+      past_since = millis();
+    }
+    if (millis() - past_since >= debounce_ms) {
+      past  = false;
+      armed = false;
+      rotate();
+    }
+  } else {past = false;}
+}
+
+// Same both ways: a quarter turn past the threshold, half past the limit. Everything gets pushed, the host has to
+// know about 180° flips too.
+void rotate() {
+  if (angle > angle_threshold) {
+    if (angle >= angle_limit) {
+      current_ori = (current_ori+2)%4;
+      offset += 180;
+    } else {
+      current_ori = (current_ori+3)%4;
+      offset += 90;
+    }
+  } else if (-angle > angle_threshold) {
+    if (-angle >= angle_limit) {
+      current_ori = (current_ori+2)%4;
+      offset -= 180;
+    } else {
+      current_ori = (current_ori+1)%4;
+      offset -= 90;
+    }
+  } else {return;}
+  offset = wrap(offset);
+  serial_changeori(DC1+current_ori);
+}
+
+// keeps angle and offset in (-180, 180], same as deviation()
+float wrap(float deg) {
+  deg = fmod(deg, 360);
+  if (deg > 180)        {deg -= 360;}
+  else if (deg <= -180) {deg += 360;}
+  return deg;
+}
+
+// Applies right away, even while running. false if it's out of range.
+bool set_tuning(int threshold, int hyst, unsigned int debounce) {
+  if (threshold < 1 || threshold > 89 || hyst >= threshold) {return false;}
+  angle_threshold = threshold;
+  angle_limit     = threshold+90;
+  hysteresis      = hyst;
+  debounce_ms     = debounce;
+  return true;
+}
+
+void send_tuning() {
+  byte payload[5] = {SO, (byte)angle_threshold, (byte)hysteresis, (byte)(debounce_ms >> 8), (byte)(debounce_ms & 0xFF)};
+  send_frame(seq_in, payload, 5);
+}
+
+// The IMU only runs while something needs it.
+bool imu_needed() {
+  return running==true || telemetry_ms > 0 || raw_ms > 0;
+}
+
+void set_telemetry(unsigned int interval) {
+  bool needed = imu_needed();
+  telemetry_ms = interval;
+  if (!needed && imu_needed()) {IMU.begin();}
+  if (needed && !imu_needed()) {IMU.end();}
+}
+
+void set_raw(unsigned int interval) {
+  bool needed = imu_needed();
+  raw_ms = interval;
+  if (!needed && imu_needed()) {IMU.begin();}
+  if (needed && !imu_needed()) {IMU.end();}
+}
+
+void send_sample(float dev) {
+  byte payload[13] = {DLE};
+  put_u32(payload+1, millis());
+  put_float(payload+5, dev);
+  put_float(payload+9, offset);
+  send_frame(0, payload, 13);
+}
+
+void send_reading(float x, float y, float z) {
+  byte payload[17] = {RS};
+  put_u32(payload+1, millis());
+  put_float(payload+5, x);
+  put_float(payload+9, y);
+  put_float(payload+13, z);
+  send_frame(0, payload, 17);
+}
+
+void put_u32(byte *p, uint32_t v) {
+  p[0] = v >> 24;
+  p[1] = v >> 16;
+  p[2] = v >> 8;
+  p[3] = v;
+}
+
+void put_float(byte *p, float f) {
+  uint32_t v;
+  memcpy(&v, &f, 4);
+  put_u32(p, v);
+}
+
 bool serial_changeori(int ori) {
-  Serial.write(ori);
+  byte seq = seq_out++;
+  push(ori, seq);
   delay(100);
   
   while (Serial.available() == 0) {
     delay(100);
-    Serial.write(ori);
+    push(ori, seq);
+  }
+
+  if (!framed) {return Serial.read() == ACK;}
+  byte payload[MAX_PAYLOAD];
+  return Serial.read() == STX && read_frame(payload) == 1 && payload[0] == ACK && seq_in == seq;
+}
+
+void push(byte code, byte seq) {
+  if (framed) {send_frame(seq, &code, 1);}
+  else        {Serial.write(code);}
+}
+
+void reply(byte code) {
+  if (framed) {send_frame(seq_in, &code, 1);}
+  else        {Serial.write(code);}
+}
+
+void hello() {
+  byte payload[MAX_PAYLOAD] = {SOH, FIRMWARE_MAJOR, FIRMWARE_MINOR, FIRMWARE_PATCH, sizeof(COMMANDS)};
+  int len = 5;
+  memcpy(payload+len, COMMANDS, sizeof(COMMANDS));
+  len += sizeof(COMMANDS);
+  memcpy(payload+len, FIRMWARE_NAME, strlen(FIRMWARE_NAME));
+  len += strlen(FIRMWARE_NAME);
+  send_frame(seq_in, payload, len);
+}
+
+void send_frame(byte seq, const byte *payload, int len) {
+  byte header[3] = {PROTOCOL_VERSION, seq, (byte)len};
+  uint16_t crc = crc16(header, 3, 0xFFFF);
+  crc = crc16(payload, len, crc);
+  Serial.write(STX);
+  Serial.write(header, 3);
+  Serial.write(payload, len);
+  Serial.write(crc >> 8);
+  Serial.write(crc & 0xFF);
+}
+
+// The rest of a frame after its STX, returns the payload length or -1 if it didn't check out.
+int read_frame(byte *payload) {
+  byte header[3]; // version, seq, length
+  for (int i = 0; i < 3; ++i) {
+    int b = read_timeout();
+    if (b < 0) {return -1;}
+    header[i] = b;
   }
+  if (header[0] != PROTOCOL_VERSION || header[2] > MAX_PAYLOAD) {return -1;}
 
-  return Serial.read() == ACK;
+  for (int i = 0; i < header[2]; ++i) {
+    int b = read_timeout();
+    if (b < 0) {return -1;}
+    payload[i] = b;
+  }
+  int hi = read_timeout(), lo = read_timeout();
+  if (hi < 0 || lo < 0) {return -1;}
+
+  uint16_t crc = crc16(header, 3, 0xFFFF);
+  crc = crc16(payload, header[2], crc);
+  if (crc != ((hi << 8) | lo)) {return -1;}
+
+  seq_in = header[1];
+  return header[2];
+}
+
+// -1 if nothing arrives within 100ms
+int read_timeout() {
+  unsigned long start = millis();
+  while (Serial.available() == 0) {
+    if (millis() - start >= 100) {return -1;}
+  }
+  return Serial.read();
+}
+
+// CRC-16/CCITT-FALSE
+uint16_t crc16(const byte *data, int len, uint16_t crc) {
+  for (int i = 0; i < len; ++i) {
+    crc ^= (uint16_t)data[i] << 8;
+    for (int j = 0; j < 8; ++j) {
+      crc = (crc & 0x8000) ? (crc << 1) ^ 0x1021 : crc << 1;
+    }
+  }
+  return crc;
 }
 
 void calibrate_IMU() {
@@ -178,11 +442,7 @@ void calibrate_IMU() {
   //Serial.println("\ncalib done");
 }
 
+// which way down is, in [-180, 180]
 float deviation(float a, float b) {
-  float deg = atan(a/b)*RAD_TO_DEG;
-  if (b <= 0) {
-    if (a >= 0) {deg += 180;}
-    else        {deg -= 180;}
-  }
-  return deg; 
+  return atan2(a, b)*RAD_TO_DEG;
 }
\ No newline at end of file
@@ -0,0 +1,316 @@
+use std::{fmt, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPort, SerialPortInfo, SerialPortType};
+use crate::{orientation::Orientation, protocol::{Client, Tuning}, config::{SerialConfig, SerialOverrides, SettleConfig, HostConfig}, display::Profiles, tracker::Decider};
+
+// One sensor and the display it rotates.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Binding {
+    #[serde(default)]
+    pub port: String, // only a fallback if usb is set
+    pub display: u32,
+    // tables have to come after the plain values for toml to write them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usb: Option<UsbId>,
+    #[serde(default, skip_serializing_if = "SerialOverrides::is_empty")]
+    pub serial: SerialOverrides,
+    #[serde(default, skip_serializing_if = "Profiles::is_empty")]
+    pub profiles: Profiles // applied along with the orientation
+}
+
+// Pins a binding to a USB device, whichever port it shows up as. Without a serial number, any board with the same VID/PID matches.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>
+}
+
+pub struct Device {
+    pub binding: Binding,
+    pub port: String,         // the port binding resolved to when it was last opened
+    pub serial: SerialConfig, // what the port gets opened with, [serial] with the binding's overrides (or the tray's changes)
+    pub tuning: Tuning,       // the threshold is sent in the DC1 handshake, the rest after it if the firmware supports SI
+    pub host: Option<HostConfig>, // Some if the host decides instead of the firmware
+    pub decider: Option<Decider>, // with `host`, while autorotating
+    pub gate: Gate,
+    pub client: Option<Client<Box<dyn SerialPort>>>, // None while disconnected
+    pub current_ori: Orientation,
+    pub autorotate: bool // wanted, gets resumed after a reconnect
+}
+
+impl Device {
+    pub fn new(binding: Binding, serial: SerialConfig, tuning: Tuning, settle: SettleConfig, host: Option<HostConfig>, current_ori: Orientation) -> Self {
+        Device {port: binding.port.clone(), binding, serial, tuning, host, decider: None, gate: Gate::new(settle), client: None, current_ori, autorotate: false}
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.as_ref().is_some_and(|client| client.is_connected())
+    }
+}
+
+// How long a rotation that failed waits before it's tried again
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Holds on to what the device wants until it's settled, see SettleConfig.
+pub struct Gate {
+    pub settle: SettleConfig,
+    pending: Option<(Orientation, Instant, Instant)>, // and when it was first & last wanted
+    last_rotation: Option<Instant>,
+    last_failure: Option<Instant>
+}
+
+impl Gate {
+    pub fn new(settle: SettleConfig) -> Self {
+        Gate {settle, pending: None, last_rotation: None, last_failure: None}
+    }
+
+    // A push, or what the decider made of a reading. Returns what was pending before if this replaced it.
+    pub fn want(&mut self, ori: Orientation, now: Instant) -> Option<Orientation> {
+        match self.pending {
+            Some((pending, _, ref mut last)) if pending == ori => {
+                *last = now;
+                None
+            }
+            pending => {
+                self.pending = Some((ori, now, now));
+                pending.map(|(pending, ..)| pending)
+            }
+        }
+    }
+
+    // What to rotate to now, if anything, followed by rotated() or retry(). Wanting `current` again means it was a bump.
+    pub fn poll(&mut self, current: Orientation, now: Instant) -> Option<Orientation> {
+        let (ori, first, last) = self.pending?;
+        if ori == current {
+            self.pending = None;
+            return None;
+        }
+        let passed = |since: Instant, ms: u16| now.saturating_duration_since(since) >= Duration::from_millis(ms as u64);
+        if !passed(last, self.settle.debounce_ms) || !passed(first, self.settle.confirm_ms) {return None}
+        if self.last_rotation.is_some_and(|t| !passed(t, self.settle.cooldown_ms)) {return None}
+        if self.last_failure.is_some_and(|t| now.saturating_duration_since(t) < RETRY_DELAY) {return None}
+
+        self.pending = None;
+        Some(ori)
+    }
+
+    // What poll() returned was rotated to, the cooldown starts now
+    pub fn rotated(&mut self, now: Instant) {
+        self.last_rotation = Some(now);
+        self.last_failure = None;
+    }
+
+    // Rotating to what poll() returned failed, it's pending again unless something else was wanted since
+    pub fn retry(&mut self, ori: Orientation, now: Instant) {
+        self.pending.get_or_insert((ori, now, now));
+        self.last_failure = Some(now);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = None;
+        self.last_failure = None;
+    }
+}
+
+impl Binding {
+    // The port the pinned USB device is on, or just `port`. If several ports match, `port` breaks the tie.
+    pub fn resolve_port(&self) -> Result<String, String> {
+        let usb = match self.usb {
+            Some(ref usb) => usb,
+            None => return Ok(self.port.clone())
+        };
+        let ports = serialport::available_ports().map_err(|e| format!("Couldn't list serial ports: {e}"))?;
+        self.pick_port(usb, &ports)
+    }
+
+    // resolve_port() out of `ports`
+    fn pick_port(&self, usb: &UsbId, ports: &[SerialPortInfo]) -> Result<String, String> {
+        let matching: Vec<_> = ports.iter().filter(|p| usb.matches(p)).map(|p| &p.port_name).collect();
+        match matching[..] {
+            [] => Err(format!("USB device `{usb}` isn't plugged in.")),
+            [port] => Ok(port.clone()),
+            _ if matching.contains(&&self.port) => Ok(self.port.clone()),
+            _ => Err(format!("`{}` ports belong to USB device `{usb}`, set its serial number to tell them apart.", matching.len()))
+        }
+    }
+}
+
+impl UsbId {
+    // None if it isn't a USB port
+    pub fn of(port: &SerialPortInfo) -> Option<Self> {
+        match port.port_type {
+            SerialPortType::UsbPort(ref usb) => Some(UsbId {vid: usb.vid, pid: usb.pid, serial: usb.serial_number.clone()}),
+            _ => None
+        }
+    }
+
+    pub fn matches(&self, port: &SerialPortInfo) -> bool {
+        match UsbId::of(port) {
+            Some(usb) => usb.vid == self.vid && usb.pid == self.pid && (self.serial.is_none() || usb.serial == self.serial),
+            None => false
+        }
+    }
+}
+// The way it's written in the config, e.g. `{vid = 0x2341, pid = 0x805a, serial = "1A2B3C4D"}`
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{vid = {:#06x}, pid = {:#06x}", self.vid, self.pid)?;
+        if let Some(ref serial) = self.serial {write!(f, ", serial = {serial:?}")?}
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Orientation::*;
+
+    fn settle(debounce_ms: u16, confirm_ms: u16, cooldown_ms: u16) -> (Gate, impl Fn(u64) -> Instant) {
+        let start = Instant::now();
+        (Gate::new(SettleConfig {debounce_ms, confirm_ms, cooldown_ms}), move |ms| start + Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn rotates_right_away_by_default() {
+        let (mut gate, at) = settle(0, 0, 0);
+        assert_eq!(gate.poll(Landscape, at(0)), None); // nothing wanted
+        assert_eq!(gate.want(Portrait, at(0)), None);
+        assert_eq!(gate.poll(Landscape, at(0)), Some(Portrait));
+        assert_eq!(gate.poll(Portrait, at(0)), None); // only once
+        gate.want(Landscape, at(1));
+        assert_eq!(gate.poll(Portrait, at(1)), Some(Landscape));
+    }
+
+    #[test]
+    fn waits_for_confirmation() {
+        let (mut gate, at) = settle(0, 400, 0);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(399)), None);
+        assert_eq!(gate.want(Portrait, at(300)), None); // again, doesn't restart it
+        assert_eq!(gate.poll(Landscape, at(400)), Some(Portrait));
+
+        // something else restarts it
+        gate.want(PortraitFlipped, at(1000));
+        assert_eq!(gate.want(LandscapeFlipped, at(1300)), Some(PortraitFlipped));
+        assert_eq!(gate.poll(Portrait, at(1400)), None);
+        assert_eq!(gate.poll(Portrait, at(1700)), Some(LandscapeFlipped));
+    }
+
+    #[test]
+    fn debounces() {
+        let (mut gate, at) = settle(300, 0, 0);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(299)), None);
+        // still being wanted pushes it back
+        for ms in [200, 400, 600] {
+            assert_eq!(gate.want(Portrait, at(ms)), None);
+            assert_eq!(gate.poll(Landscape, at(ms + 299)), None);
+        }
+        assert_eq!(gate.poll(Landscape, at(900)), Some(Portrait));
+
+        // confirm_ms still counts from the first one
+        let (mut gate, at) = settle(100, 500, 0);
+        gate.want(Portrait, at(0));
+        gate.want(Portrait, at(450));
+        assert_eq!(gate.poll(Landscape, at(500)), None);
+        assert_eq!(gate.poll(Landscape, at(550)), Some(Portrait));
+    }
+
+    #[test]
+    fn cancels() {
+        let (mut gate, at) = settle(0, 400, 0);
+        // back to where it was, a bump
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.want(Landscape, at(100)), Some(Portrait));
+        assert_eq!(gate.poll(Landscape, at(100)), None);
+        assert_eq!(gate.poll(Landscape, at(1000)), None);
+
+        gate.want(Portrait, at(2000));
+        gate.clear();
+        assert_eq!(gate.poll(Landscape, at(3000)), None);
+        assert_eq!(gate.want(Portrait, at(3000)), None); // nothing was pending
+    }
+
+    #[test]
+    fn cools_down() {
+        let (mut gate, at) = settle(0, 0, 2000);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(0)), Some(Portrait)); // the first one doesn't wait
+        gate.rotated(at(0));
+
+        gate.want(Landscape, at(500));
+        assert_eq!(gate.poll(Portrait, at(1999)), None);
+        assert_eq!(gate.poll(Portrait, at(2000)), Some(Landscape));
+
+        // confirmed before the cooldown's over, it still waits for it
+        let (mut gate, at) = settle(0, 400, 2000);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(400)), Some(Portrait));
+        gate.rotated(at(400));
+        gate.want(Landscape, at(500));
+        assert_eq!(gate.poll(Portrait, at(900)), None);
+        assert_eq!(gate.poll(Portrait, at(2400)), Some(Landscape));
+    }
+
+    #[test]
+    fn retries_failed_rotations() {
+        let (mut gate, at) = settle(0, 0, 2000);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(0)), Some(Portrait));
+        gate.retry(Portrait, at(0));
+        // no cooldown since nothing was rotated, but not every tick either
+        assert_eq!(gate.poll(Landscape, at(999)), None);
+        assert_eq!(gate.poll(Landscape, at(1000)), Some(Portrait));
+        gate.rotated(at(1000));
+        assert_eq!(gate.poll(Portrait, at(1000)), None);
+
+        // something newer wins over the retry
+        let (mut gate, at) = settle(0, 0, 0);
+        gate.want(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(0)), Some(Portrait));
+        gate.want(PortraitFlipped, at(0));
+        gate.retry(Portrait, at(0));
+        assert_eq!(gate.poll(Landscape, at(1000)), Some(PortraitFlipped));
+
+        // going back cancels it
+        gate.retry(PortraitFlipped, at(1000));
+        gate.want(Landscape, at(1100));
+        assert_eq!(gate.poll(Landscape, at(3000)), None);
+    }
+
+    fn port(name: &str, vid: u16, pid: u16, serial: Option<&str>) -> SerialPortInfo {
+        let usb = serialport::UsbPortInfo {vid, pid, serial_number: serial.map(str::to_string), manufacturer: None, product: None};
+        SerialPortInfo {port_name: name.to_string(), port_type: SerialPortType::UsbPort(usb)}
+    }
+
+    #[test]
+    fn picks_ports() {
+        let ports = [
+            SerialPortInfo {port_name: "COM1".to_string(), port_type: SerialPortType::Unknown},
+            port("COM3", 0x2341, 0x0043, Some("A")),
+            port("COM4", 0x2341, 0x0043, Some("B")),
+            port("COM5", 0x1a86, 0x7523, None)
+        ];
+        let pick = |port: &str, vid, pid, serial: Option<&str>| {
+            let usb = UsbId {vid, pid, serial: serial.map(str::to_string)};
+            let binding = Binding {port: port.to_string(), display: 1, usb: Some(usb.clone()), serial: SerialOverrides::default(), profiles: Profiles::default()};
+            binding.pick_port(&usb, &ports)
+        };
+
+        assert!(pick("COM3", 0x2341, 0x0044, None).is_err()); // not plugged in
+        assert_eq!(pick("", 0x1a86, 0x7523, None), Ok("COM5".to_string()));
+        assert_eq!(pick("COM1", 0x1a86, 0x7523, None), Ok("COM5".to_string())); // wherever it shows up
+        // several, `port` breaks the tie
+        assert_eq!(pick("COM4", 0x2341, 0x0043, None), Ok("COM4".to_string()));
+        assert_eq!(pick("COM3", 0x2341, 0x0043, None), Ok("COM3".to_string()));
+        assert_eq!(pick("COM5", 0x2341, 0x0043, None), Err("`2` ports belong to USB device `{vid = 0x2341, pid = 0x0043}`, set its serial number to tell them apart.".to_string()));
+        // the serial number doesn't need one
+        assert_eq!(pick("COM3", 0x2341, 0x0043, Some("B")), Ok("COM4".to_string()));
+        assert!(pick("COM3", 0x2341, 0x0043, Some("C")).is_err());
+        assert!(pick("COM5", 0x1a86, 0x7523, Some("A")).is_err()); // a board without one only matches without one
+    }
+}
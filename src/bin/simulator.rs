@@ -0,0 +1,103 @@
+#[cfg(unix)]
+use std::{io::{self, BufRead, BufReader}, fs::File, sync::mpsc, thread, time::Duration};
+#[cfg(unix)]
+use serialport::{SerialPort, TTYPort};
+#[cfg(unix)]
+use everythingdoer::simulator::Device;
+
+// Tilt script, one command per line:
+//   tilt <deg>   set the angle the IMU reports (a bare number works too)
+//   wait <ms>    pause before the next command
+//   quit         stop the simulator
+// Empty lines and lines starting with # are ignored.
+
+#[cfg(unix)]
+enum Command {
+    Tilt(f32),
+    Quit
+}
+
+#[cfg(unix)]
+fn main() {
+    let args = clap::Command::new("simulator")
+        .about("Emulates the monitor-autorotate firmware on a pseudo-terminal")
+        .arg(clap::Arg::new("script").long("script").takes_value(true).help("Read tilt commands from a file instead of stdin"))
+        .arg(clap::Arg::new("link").long("link").takes_value(true).help("Create a symlink to the pty at this path"))
+        .get_matches();
+
+    let (mut master, mut slave) = TTYPort::pair().expect("Couldn't create pty");
+    slave.set_exclusive(false).unwrap();
+    master.set_timeout(Duration::from_millis(10)).unwrap();
+    let name = slave.name().unwrap();
+
+    if let Some(link) = args.value_of("link") {
+        _=std::fs::remove_file(link);
+        std::os::unix::fs::symlink(&name, link).expect("Couldn't create symlink");
+        println!("Simulating device on \"{name}\" (linked at \"{link}\").");
+    } else {
+        println!("Simulating device on \"{name}\".");
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let script: Box<dyn BufRead + Send> = match args.value_of("script") {
+        Some(path) => Box::new(BufReader::new(File::open(path).expect("Couldn't open script"))),
+        None => Box::new(BufReader::new(io::stdin()))
+    };
+    thread::spawn(move || for line in script.lines() {
+        let line = line.unwrap();
+        let mut words = line.split_whitespace();
+        let cmd = match (words.next(), words.next()) {
+            (None, _) => continue,
+            (Some(w), _) if w.starts_with('#') => continue,
+            (Some("tilt"), Some(v)) | (Some(v), None) if v.parse::<f32>().is_ok() => Command::Tilt(v.parse().unwrap()),
+            (Some("wait"), Some(v)) if v.parse::<u64>().is_ok() => {
+                thread::sleep(Duration::from_millis(v.parse().unwrap()));
+                continue;
+            }
+            (Some("quit"), None) => Command::Quit,
+            _ => {
+                eprintln!("Invalid command \"{line}\".");
+                continue;
+            }
+        };
+        if tx.send(cmd).is_err() {break}
+    });
+
+    let mut device = Device::new(master);
+    let (mut running, mut ori, mut tuning) = (device.running, device.tracker.orientation, device.tuning());
+    loop {
+        while let Ok(cmd) = rx.try_recv() {
+            match cmd {
+                Command::Tilt(deg) => {
+                    println!("Tilt: {deg}°");
+                    device.tilt = deg;
+                }
+                Command::Quit => return
+            }
+        }
+
+        if let Err(e) = device.step() {
+            eprintln!("Couldn't communicate: {e}");
+            break;
+        }
+
+        if device.running != running {
+            running = device.running;
+            if running {println!("Autorotation started (orientation {}, threshold {}°).", device.tracker.orientation as u8, device.tracker.tuning.threshold);}
+            else {println!("Autorotation stopped.");}
+        }
+        if device.tuning() != tuning {
+            tuning = device.tuning();
+            println!("Tuning: {tuning}.");
+        }
+        if device.tracker.orientation != ori {
+            ori = device.tracker.orientation;
+            println!("Orientation: {ori:?}");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("The simulator needs a pseudo-terminal, which is only available on unix.");
+}
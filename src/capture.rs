@@ -0,0 +1,126 @@
+use std::{fmt, fs::{File, OpenOptions}, io::{self, BufRead, BufReader, Write}, path::Path, sync::{Arc, Mutex, PoisonError}};
+use chrono::{Local, NaiveDateTime};
+use crate::protocol::Mode;
+
+// Every byte that goes over the wire, one line per read or write, appended to a file:
+//   2026-10-18 12:00:00.123456 COM4 > 02 02 01 01 16 1f 5e
+//   2026-10-18 12:00:00.125012 COM4 < 02 02 01 01 06 0d 6f
+//   2026-10-18 12:00:00.125100 COM4 mode framed
+// > is host to device, < device to host. Mode switches are recorded so replays decode like the client did.
+#[derive(Clone)]
+pub struct Capture(Arc<Mutex<File>>);
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Event {
+    Sent(Vec<u8>),
+    Received(Vec<u8>),
+    Mode(Mode)
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Record {
+    pub time: NaiveDateTime,
+    pub port: String,
+    pub event: Event
+}
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+impl Capture {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Capture(Arc::new(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?))))
+    }
+
+    // A capture that can't be written to shouldn't take the connection down with it, so errors are ignored.
+    pub fn record(&self, port: &str, event: Event) {
+        let record = Record {time: Local::now().naive_local(), port: port.to_string(), event};
+        _=writeln!(self.0.lock().unwrap_or_else(PoisonError::into_inner), "{record}");
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ", self.time.format(TIME_FORMAT), self.port)?;
+        let (dir, bytes) = match self.event {
+            Event::Sent(ref bytes) => (">", bytes),
+            Event::Received(ref bytes) => ("<", bytes),
+            Event::Mode(mode) => return write!(f, "mode {mode}")
+        };
+        write!(f, "{dir}")?;
+        for b in bytes {write!(f, " {b:02x}")?}
+        Ok(())
+    }
+}
+
+impl Record {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let time = NaiveDateTime::parse_from_str(&format!("{} {}", parts.next()?, parts.next()?), TIME_FORMAT).ok()?;
+        let port = parts.next()?.to_string();
+        let dir = parts.next()?;
+        let rest: Vec<&str> = parts.collect();
+        let bytes = || rest.iter().map(|b| u8::from_str_radix(b, 16).ok()).collect::<Option<Vec<u8>>>();
+
+        let event = match (dir, &rest[..]) {
+            (">", _) => Event::Sent(bytes()?),
+            ("<", _) => Event::Received(bytes()?),
+            ("mode", ["legacy"]) => Event::Mode(Mode::Legacy),
+            ("mode", ["framed"]) => Event::Mode(Mode::Framed),
+            _ => return None
+        };
+        Some(Record {time, port, event})
+    }
+}
+
+// Empty lines are skipped, anything else that doesn't parse is an error.
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let mut ret = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {continue}
+        match Record::parse(&line) {
+            Some(record) => ret.push(record),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {line:?}", i + 1)))
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn records_round_trip() {
+        let time = NaiveDateTime::parse_from_str("2026-10-18 12:00:00.125012", TIME_FORMAT).unwrap();
+        for (event, line) in [(Event::Sent(vec![0x02, 0x02, 0x01, 0x01, 0x16, 0x1f, 0x5e]), "2026-10-18 12:00:00.125012 COM4 > 02 02 01 01 16 1f 5e"),
+                              (Event::Received(vec![0x06]), "2026-10-18 12:00:00.125012 COM4 < 06"),
+                              (Event::Received(vec![]), "2026-10-18 12:00:00.125012 COM4 <"),
+                              (Event::Mode(Mode::Legacy), "2026-10-18 12:00:00.125012 COM4 mode legacy"),
+                              (Event::Mode(Mode::Framed), "2026-10-18 12:00:00.125012 COM4 mode framed")] {
+            let record = Record {time, port: "COM4".to_string(), event};
+            assert_eq!(record.to_string(), line);
+            assert_eq!(Record::parse(line), Some(record));
+        }
+        for bad in ["2026-10-18 COM4 > 06", "2026-10-18 12:00:00.125012 COM4 > 6g", "2026-10-18 12:00:00.125012 COM4 > 100",
+                    "2026-10-18 12:00:00.125012 COM4 mode fast", "2026-10-18 12:00:00.125012 COM4 ? 06", "2026-10-18 12:00:00.125012 COM4"] {
+            assert_eq!(Record::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn reads_files() {
+        let path = std::env::temp_dir().join(format!("everythingdoer-capture-test-{}", std::process::id()));
+        let good = "2026-10-18 12:00:00.123456 COM4 > 16\n\n2026-10-18 12:00:00.125012 COM4 < 06\n   \n";
+        fs::write(&path, good).unwrap();
+        let records = read(&path).unwrap();
+        assert_eq!(records.iter().map(|r| &r.event).collect::<Vec<_>>(), [&Event::Sent(vec![0x16]), &Event::Received(vec![0x06])]);
+
+        fs::write(&path, format!("{good}garbage\n")).unwrap();
+        let e = read(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "line 5: \"garbage\"");
+        fs::remove_file(&path).unwrap();
+    }
+}
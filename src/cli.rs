@@ -0,0 +1,190 @@
+use std::path::Path;
+use chrono::Local;
+use clap::{Arg, ArgMatches, Command};
+use everythingdoer::{orientation::Orientation, display, config::{Config, SerialConfig}, lock::{self, Lock}, log::Level, error, info};
+use crate::{print_ports, open_client, test_comms, query_status, recalibrate, rotate_monitor, calc_factorial, write_factorial, print_factorial, replay_capture, tune, set_lock, console, telemetry};
+
+pub fn command() -> Command<'static> {
+    let port = Arg::new("port").long("port").short('p').takes_value(true).help("Serial port to use instead of the ones bound in the config");
+
+    Command::new("everythingdoer")
+        .about("Tray app for autorotating monitors with an arduino, and a few other things")
+        .arg(Arg::new("config").long("config").short('c').takes_value(true).global(true).help("Config file to use instead of the default one"))
+        .arg(Arg::new("headless").long("headless").help("Autorotate without the tray icon or console window"))
+        .arg(Arg::new("log").long("log").takes_value(true).global(true).help("File to append the log to, instead of stdout when headless"))
+        .arg(Arg::new("capture").long("capture").takes_value(true).global(true).help("File to append every byte sent and received over serial to, see the replay subcommand"))
+        .arg(Arg::new("log-level").long("log-level").takes_value(true).global(true).default_value("info").value_parser(clap::value_parser!(Level)).help("error, warn, info or debug"))
+        .subcommand(Command::new("ports").about("Print serial ports"))
+        .subcommand(Command::new("ping").about("Test comms (SYN)").arg(port.clone()))
+        .subcommand(Command::new("status").about("Query whether autorotation is running (ENQ), and print display orientations and the orientation lock").arg(port.clone()))
+        .subcommand(Command::new("recalibrate").about("Recalibrate the IMU (DC3)").arg(port.clone()))
+        .subcommand(Command::new("tune").about("Print the firmware's threshold, hysteresis and debounce time (SO), or change them without stopping autorotation (SI)")
+            .arg(port.clone())
+            .arg(Arg::new("threshold").long("threshold").takes_value(true).value_parser(clap::value_parser!(u8)).help("Degrees of tilt it takes to rotate"))
+            .arg(Arg::new("hysteresis").long("hysteresis").takes_value(true).value_parser(clap::value_parser!(u8)).help("Degrees the tilt has to come back by before it can rotate again"))
+            .arg(Arg::new("debounce").long("debounce").takes_value(true).value_parser(clap::value_parser!(u16)).help("Milliseconds the tilt has to stay past the threshold")))
+        .subcommand(Command::new("telemetry").about("Graph the IMU's angle live (DLE), to tune the threshold or spot calibration drift")
+            .arg(port.clone())
+            .arg(Arg::new("interval").long("interval").short('i').takes_value(true).default_value("50").value_parser(clap::value_parser!(u16).range(1..)).help("Milliseconds between samples"))
+            .arg(Arg::new("csv").long("csv").takes_value(true).help("File to write the samples to as well")))
+        .subcommand(Command::new("console").about("Interactive serial terminal, for debugging the firmware").arg(port.help("Serial port to use instead of the first one bound in the config")))
+        .subcommand(Command::new("lock").about("Keep the monitors in their current orientation without turning autorotation off, everywhere it's running")
+            .arg(Arg::new("for").long("for").takes_value(true).value_parser(lock::parse_duration).help("Unlock again after this long, e.g. 90s, 15m or 1h30m (a bare number is minutes)")))
+        .subcommand(Command::new("unlock").about("Let the monitors autorotate again"))
+        .subcommand(Command::new("rotate").about("Rotate displays")
+            .arg(Arg::new("orientation").required(true).value_parser(clap::value_parser!(Orientation)).help("landscape, portrait, landscape-flipped or portrait-flipped"))
+            .arg(Arg::new("display").long("display").short('d').takes_value(true).value_parser(clap::value_parser!(u32)).help("Display to rotate instead of the ones bound in the config")))
+        .subcommand(Command::new("replay").about("Decode a capture made with --capture")
+            .arg(Arg::new("file").required(true)))
+        .subcommand(Command::new("factorial").about("Calculate a factorial")
+            .arg(Arg::new("n").required(true).value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("out").long("out").short('o').takes_value(true).help("File to write the result to instead of printing it")))
+}
+
+// true on success
+pub fn run(args: &ArgMatches, config: &Config, config_path: &Path) -> bool {
+    // with the serial settings to open them with, a --port that's bound gets its binding's
+    let ports = |sub: &ArgMatches| -> Vec<(Result<String, String>, SerialConfig)> {
+        match sub.get_one::<String>("port") {
+            Some(p) => {
+                let serial = config.autorotate.bindings.iter().find(|b| b.port == *p).map_or_else(|| config.serial.clone(), |b| config.serial_for(b));
+                vec![(Ok(p.clone()), serial)]
+            }
+            None => config.autorotate.bindings.iter().map(|b| (b.resolve_port(), config.serial_for(b))).collect()
+        }
+    };
+    // a pinned USB device that isn't plugged in fails like a port that doesn't open
+    let open = |(port, serial): &(Result<String, String>, SerialConfig)| match port {
+        Ok(port) => open_client(port, serial),
+        Err(e) => {
+            error!(Serial, "{e}");
+            None
+        }
+    };
+
+    match args.subcommand() {
+        Some(("ports", _)) => {
+            print_ports();
+            true
+        }
+        Some(("ping", sub)) => ports(sub).iter().fold(true, |ok, port| match open(port) {
+            Some(mut client) => test_comms(&mut client) && ok,
+            None => false
+        }),
+        Some(("status", sub)) => {
+            let mut ok = ports(sub).iter().fold(true, |ok, port| match open(port) {
+                Some(mut client) => query_status(&mut client).is_some() && ok,
+                None => false
+            });
+
+            let lock_path = Lock::path(config_path);
+            match Lock::load(&lock_path) {
+                Ok(lock) => info!(Display, "Orientation `{}`.", lock.at(Local::now())),
+                Err(e) => {
+                    error!(Display, "Couldn't read `{}`: {e}", lock_path.display());
+                    ok = false;
+                }
+            }
+            match display::native() {
+                Ok(display) => for b in &config.autorotate.bindings {
+                    match display.orientation(b.display) {
+                        Ok(ori) => info!(Display, "Display `{}`: `{ori:?}`", b.display),
+                        Err(e) => {
+                            error!(Display, "Display `{}`: {e}", b.display);
+                            ok = false;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(Display, "Couldn't access displays: {e}");
+                    ok = false;
+                }
+            }
+            ok
+        }
+        Some(("recalibrate", sub)) => ports(sub).iter().fold(true, |ok, port| match open(port) {
+            Some(mut client) => recalibrate(&mut client) && ok,
+            None => false
+        }),
+        Some(("tune", sub)) => {
+            let (threshold, hysteresis, debounce_ms) = (sub.get_one::<u8>("threshold").copied(), sub.get_one::<u8>("hysteresis").copied(), sub.get_one::<u16>("debounce").copied());
+            ports(sub).iter().fold(true, |ok, port| match open(port) {
+                Some(mut client) => tune(&mut client, threshold, hysteresis, debounce_ms) && ok,
+                None => false
+            })
+        }
+        Some(("telemetry", sub)) => match ports(sub).into_iter().next() {
+            Some(ref port @ (Ok(ref name), _)) => match open(port) {
+                Some(mut client) => telemetry::run(&mut client, name, *sub.get_one::<u16>("interval").unwrap(), sub.get_one::<String>("csv").map(Path::new), config.autorotate.tuning()),
+                None => false
+            }
+            Some(ref port) => open(port).is_some(), // logs the error
+            None => {
+                error!(Config, "No port given, and none are bound in the config.");
+                false
+            }
+        }
+        Some(("console", sub)) => match ports(sub).into_iter().next() {
+            Some((Ok(port), serial)) => console::run(&port, &serial),
+            Some((Err(e), _)) => {
+                error!(Serial, "{e}");
+                false
+            }
+            None => {
+                error!(Config, "No port given, and none are bound in the config.");
+                false
+            }
+        }
+        Some(("lock", sub)) => {
+            let lock = match sub.get_one::<chrono::Duration>("for") {
+                Some(&duration) => match Local::now().checked_add_signed(duration) {
+                    Some(until) => Lock::Until(until),
+                    None => {
+                        error!(Display, "Can't lock for that long.");
+                        return false;
+                    }
+                }
+                None => Lock::On
+            };
+            set_lock(&Lock::path(config_path), lock)
+        }
+        Some(("unlock", _)) => set_lock(&Lock::path(config_path), Lock::Off),
+        Some(("rotate", sub)) => {
+            let ori = *sub.get_one::<Orientation>("orientation").unwrap();
+            let ids = match sub.get_one::<u32>("display") {
+                Some(&id) => vec![id],
+                None => config.autorotate.bindings.iter().map(|b| b.display).collect()
+            };
+
+            match display::native() {
+                Ok(mut display) => ids.into_iter().fold(true, |ok, id| {
+                    let mut current_ori = ori;
+                    let profile = config.autorotate.bindings.iter().find(|b| b.display == id).and_then(|b| b.profiles.get(ori));
+                    rotate_monitor(&mut *display, id, ori, profile, &mut current_ori) && ok
+                }),
+                Err(e) => {
+                    error!(Display, "Couldn't access displays: {e}");
+                    false
+                }
+            }
+        }
+        Some(("replay", sub)) => replay_capture(Path::new(sub.get_one::<String>("file").unwrap())),
+        Some(("factorial", sub)) => {
+            let n = *sub.get_one::<u64>("n").unwrap();
+            let (reslv, resl_sn1, resl_sn2, calc_time) = calc_factorial(n, config.factorial_thread_count);
+
+            match sub.get_one::<String>("out") {
+                Some(path) => match write_factorial(Path::new(path), &reslv, &resl_sn1, &resl_sn2, calc_time) {
+                    Ok(()) => info!(Factorial, "Wrote the result to `{path}`."),
+                    Err(e) => {
+                        error!(Factorial, "Couldn't write to `{path}`: {e}");
+                        return false;
+                    }
+                }
+                None => print_factorial(&reslv, &resl_sn1, &resl_sn2, calc_time)
+            }
+            true
+        }
+        _ => unreachable!()
+    }
+}
@@ -0,0 +1,483 @@
+use std::{fs, io, fmt, path::{Path, PathBuf}, time::Duration, collections::HashSet};
+use serde::{Deserialize, Serialize};
+use crate::{autorotate::Binding, protocol::Tuning, lock::Hotkey, display::{Profiles, DPI_SCALES}, orientation::Orientation};
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub factorial_thread_count: u64,
+    pub serial: SerialConfig,
+    pub autorotate: AutorotateConfig
+}
+// How ports get opened, bindings can override any of it.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: u8, // 5-8
+    pub parity: Parity,
+    pub stop_bits: u8, // 1 or 2
+    pub flow_control: FlowControl,
+    pub dtr: bool, // set after opening, most arduinos reset when it goes up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rts: Option<bool>, // left alone unless set
+    pub read_timeout_ms: u64, // how often the reader thread checks whether the client was dropped
+    pub ack_timeout_ms: u64,
+    pub protocol: Protocol
+}
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {None, Odd, Even}
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Software, // XON/XOFF
+    Hardware  // RTS/CTS
+}
+// A binding's `serial` table, whatever's left out comes from [serial].
+#[derive(Clone, Default, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")] pub baud_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub data_bits: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub parity: Option<Parity>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub stop_bits: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub flow_control: Option<FlowControl>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub dtr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub rts: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub read_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub ack_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub protocol: Option<Protocol>
+}
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Auto,   // framed if the firmware supports it, legacy otherwise
+    Legacy, // bare control bytes
+    Framed
+}
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutorotateConfig {
+    pub threshold_deg: u8,
+    pub hysteresis_deg: u8,
+    pub debounce_ms: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_hotkey: Option<String>, // toggles the orientation lock, e.g. "ctrl+alt+l" (windows only)
+    // tables have to come after the plain values for toml to write them
+    pub settle: SettleConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<HostConfig>, // decide on the host instead of in the firmware
+    pub bindings: Vec<Binding>
+}
+// [autorotate.settle]: what the host waits for before it actually rotates, on top of the firmware's debounce, so bumping
+// the monitor arm doesn't make it flicker. Going back to the current orientation in the meantime cancels the rotation.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettleConfig {
+    pub debounce_ms: u16, // since the last push for the new orientation
+    pub confirm_ms: u16,  // since the first one
+    pub cooldown_ms: u16  // since the last rotation
+}
+// [autorotate.host]: the firmware only streams the accelerometer's readings, the tuning above gets applied to them here.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    pub interval_ms: u16, // between readings
+    pub filter: Filter
+}
+// Smooths x and y separately before they become an angle, so there's nothing to go wrong where it wraps around.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Filter {
+    None,
+    Average {samples: u16}, // of the last few readings
+    LowPass {time_constant_ms: u16} // exponential, how long it takes to get ~63% of the way to a new reading
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            factorial_thread_count: 32,
+            serial: SerialConfig::default(),
+            autorotate: AutorotateConfig::default()
+        }
+    }
+}
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+            dtr: true,
+            rts: None,
+            read_timeout_ms: 100,
+            ack_timeout_ms: 10000,
+            protocol: Protocol::Auto
+        }
+    }
+}
+impl Default for AutorotateConfig {
+    fn default() -> Self {
+        AutorotateConfig {
+            threshold_deg: 65,
+            hysteresis_deg: 5,
+            debounce_ms: 0,
+            lock_hotkey: None,
+            settle: SettleConfig::default(),
+            host: None,
+            bindings: vec![Binding {port: "COM4".to_string(), usb: None, serial: SerialOverrides::default(), display: 1, profiles: Profiles::default()}]
+        }
+    }
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        HostConfig {interval_ms: 20, filter: Filter::Average {samples: 10}}
+    }
+}
+
+impl Config {
+    // ~/.config/everythingdoer/config.toml, %APPDATA%\everythingdoer\config.toml
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("everythingdoer").join("config.toml"))
+    }
+
+    // Without an explicit path, a missing file is created with the defaults.
+    pub fn load(path: Option<&Path>) -> Result<(Self, PathBuf), Error> {
+        let (path, explicit) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => (Self::default_path().ok_or_else(|| Error::Invalid(PathBuf::new(), "Couldn't find the config directory.".to_string()))?, false)
+        };
+        Ok((Self::load_from(&path, !explicit)?, path))
+    }
+
+    fn load_from(path: &Path, create: bool) -> Result<Self, Error> {
+        if create && !path.exists() {
+            let config = Config::default();
+            config.save(path)?;
+            return Ok(config);
+        }
+
+        let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        let config: Config = toml::from_str(&text).map_err(|e| Error::Parse(path.to_path_buf(), e))?;
+        config.validate().map_err(|e| Error::Invalid(path.to_path_buf(), e))?;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        }
+        fs::write(path, toml::to_string_pretty(self).unwrap()).map_err(|e| Error::Io(path.to_path_buf(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.factorial_thread_count == 0 {
+            return Err("factorial_thread_count must be at least 1.".to_string());
+        }
+        self.serial.validate("serial")?;
+        if !(1..90).contains(&self.autorotate.threshold_deg) { // the firmware flips 180° past threshold+90
+            return Err(format!("autorotate.threshold_deg must be between 1 and 89, got {}.", self.autorotate.threshold_deg));
+        }
+        if self.autorotate.hysteresis_deg >= self.autorotate.threshold_deg {
+            return Err(format!("autorotate.hysteresis_deg must be less than threshold_deg, got {}.", self.autorotate.hysteresis_deg));
+        }
+        if let Some(host) = self.autorotate.host {
+            if host.interval_ms == 0 {
+                return Err("autorotate.host.interval_ms must be at least 1.".to_string());
+            }
+            if host.filter == (Filter::Average {samples: 0}) {
+                return Err("autorotate.host.filter.samples must be at least 1.".to_string());
+            }
+        }
+
+        if let Some(ref hotkey) = self.autorotate.lock_hotkey {
+            hotkey.parse::<Hotkey>().map_err(|e| format!("autorotate.lock_hotkey: {e}"))?;
+        }
+
+        let (mut ports, mut usbs, mut displays) = (HashSet::new(), HashSet::new(), HashSet::new());
+        let mut primary = None;
+        for (i, b) in self.autorotate.bindings.iter().enumerate() {
+            match b.usb {
+                Some(ref usb) => if !usbs.insert(usb) {
+                    return Err(format!("USB device {usb} is bound more than once."));
+                }
+                None if b.port.is_empty() => return Err(format!("The binding for display {} needs a port or a usb device.", b.display)),
+                None => if !ports.insert(&b.port) {
+                    return Err(format!("Serial port \"{}\" is bound more than once.", b.port));
+                }
+            }
+            self.serial_for(b).validate(&format!("autorotate.bindings[{i}].serial"))?;
+            if !displays.insert(b.display) {
+                return Err(format!("Display {} is bound more than once.", b.display));
+            }
+            b.profiles.validate(&format!("autorotate.bindings[{i}].profiles"))?;
+            // they'd take it from each other on every rotation
+            if Orientation::ALL.iter().any(|&ori| b.profiles.get(ori).is_some_and(|p| p.primary == Some(true))) {
+                if let Some(other) = primary.replace(b.display) {
+                    return Err(format!("Displays {other} and {} are both made primary, only one binding's profiles can do that.", b.display));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // [serial] with the binding's own settings on top
+    pub fn serial_for(&self, binding: &Binding) -> SerialConfig {
+        binding.serial.apply(&self.serial)
+    }
+}
+
+impl AutorotateConfig {
+    pub fn tuning(&self) -> Tuning {
+        Tuning {threshold: self.threshold_deg, hysteresis: self.hysteresis_deg, debounce_ms: self.debounce_ms}
+    }
+}
+
+impl Profiles {
+    // what is where it came from in the config, like SerialConfig::validate()
+    fn validate(&self, what: &str) -> Result<(), String> {
+        for ori in Orientation::ALL {
+            let Some(profile) = self.get(ori) else {continue};
+            let what = format!("{what}.{}", ori.name());
+            if let Some((w, h)) = profile.resolution {
+                if w == 0 || h == 0 {
+                    return Err(format!("{what}.resolution must be at least 1x1, got {w}x{h}."));
+                }
+                // easy to copy from the other orientation by mistake
+                let portrait = matches!(ori, Orientation::Portrait | Orientation::PortraitFlipped);
+                if (portrait && w > h) || (!portrait && h > w) {
+                    return Err(format!("{what}.resolution is the width & height in that orientation, {w}x{h} doesn't fit it."));
+                }
+            }
+            if profile.refresh_hz == Some(0) {
+                return Err(format!("{what}.refresh_hz must be at least 1."));
+            }
+            if let Some(scale) = profile.scale_percent.filter(|scale| !DPI_SCALES.contains(scale)) {
+                return Err(format!("{what}.scale_percent must be one of {DPI_SCALES:?}, got {scale}."));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SerialConfig {
+    pub fn ack_timeout(&self) -> Duration {
+        Duration::from_millis(self.ack_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+
+    // what is where it came from in the config, e.g. "serial"
+    fn validate(&self, what: &str) -> Result<(), String> {
+        if self.baud_rate == 0 {
+            return Err(format!("{what}.baud_rate must be at least 1."));
+        }
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(format!("{what}.data_bits must be between 5 and 8, got {}.", self.data_bits));
+        }
+        if !(1..=2).contains(&self.stop_bits) {
+            return Err(format!("{what}.stop_bits must be 1 or 2, got {}.", self.stop_bits));
+        }
+        if self.read_timeout_ms == 0 {
+            return Err(format!("{what}.read_timeout_ms must be at least 1."));
+        }
+        if self.ack_timeout_ms == 0 {
+            return Err(format!("{what}.ack_timeout_ms must be at least 1."));
+        }
+        Ok(())
+    }
+}
+
+impl SerialOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, serial: &SerialConfig) -> SerialConfig {
+        SerialConfig {
+            baud_rate: self.baud_rate.unwrap_or(serial.baud_rate),
+            data_bits: self.data_bits.unwrap_or(serial.data_bits),
+            parity: self.parity.unwrap_or(serial.parity),
+            stop_bits: self.stop_bits.unwrap_or(serial.stop_bits),
+            flow_control: self.flow_control.unwrap_or(serial.flow_control),
+            dtr: self.dtr.unwrap_or(serial.dtr),
+            rts: self.rts.or(serial.rts),
+            read_timeout_ms: self.read_timeout_ms.unwrap_or(serial.read_timeout_ms),
+            ack_timeout_ms: self.ack_timeout_ms.unwrap_or(serial.ack_timeout_ms),
+            protocol: self.protocol.unwrap_or(serial.protocol)
+        }
+    }
+}
+
+impl From<Parity> for serialport::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd  => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even
+        }
+    }
+}
+impl From<FlowControl> for serialport::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None     => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(p, e) => write!(f, "Couldn't access config \"{}\": {e}", p.display()),
+            Error::Parse(p, e) => write!(f, "Couldn't parse config \"{}\": {e}", p.display()),
+            Error::Invalid(p, e) => write!(f, "Invalid config \"{}\": {e}", p.display())
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{autorotate::UsbId, display::Profile};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("everythingdoer-config-test-{}", std::process::id())).join(name)
+    }
+
+    fn load_text(name: &str, text: &str) -> Result<Config, Error> {
+        let path = temp_path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+        let config = Config::load(Some(&path)).map(|(config, _)| config);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn creates_default_file() {
+        let path = temp_path("default").join("config.toml");
+        assert!(matches!(Config::load_from(&path, false), Err(Error::Io(..)))); // only without an explicit path
+        assert_eq!(Config::load_from(&path, true).unwrap(), Config::default());
+        assert!(path.exists());
+        assert_eq!(Config::load_from(&path, false).unwrap(), Config::default());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut config = Config::default();
+        config.serial.parity = Parity::Even;
+        config.serial.rts = Some(false);
+        config.autorotate.settle = SettleConfig {debounce_ms: 100, confirm_ms: 400, cooldown_ms: 2000};
+        config.autorotate.host = Some(HostConfig {interval_ms: 50, filter: Filter::LowPass {time_constant_ms: 200}});
+        config.autorotate.lock_hotkey = Some("ctrl+alt+l".to_string());
+        config.autorotate.bindings[0].usb = Some(UsbId {vid: 0x2341, pid: 0x0043, serial: Some("7573".to_string())});
+        config.autorotate.bindings[0].serial.baud_rate = Some(115200);
+        config.autorotate.bindings[0].profiles.portrait = Some(Profile {refresh_hz: Some(60), ..Profile::default()});
+        let path = temp_path("round-trip.toml");
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(Some(&path)).unwrap(), (config, path.clone()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        for (name, text) in [("top", "factorial_threads = 4"), ("serial", "[serial]\nbaud = 9600"), ("settle", "[autorotate.settle]\ndelay_ms = 100"),
+                             ("binding", "[[autorotate.bindings]]\nport = \"COM4\"\ndisplay = 1\nmonitor = 2")] {
+            assert!(matches!(load_text(name, text), Err(Error::Parse(..))), "{text}");
+        }
+        assert!(matches!(load_text("parity", "[serial]\nparity = \"mark\""), Err(Error::Parse(..))));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let bindings = "[[autorotate.bindings]]\nport = \"COM4\"\ndisplay = 1\n[[autorotate.bindings]]";
+        for text in ["factorial_thread_count = 0", "[serial]\ndata_bits = 9", "[serial]\nstop_bits = 0", "[serial]\nack_timeout_ms = 0",
+                     "[autorotate]\nthreshold_deg = 90", "[autorotate]\nhysteresis_deg = 65", "[autorotate]\nlock_hotkey = \"ctrl+nope\"",
+                     "[autorotate.host]\ninterval_ms = 0", "[autorotate.host]\nfilter = {kind = \"average\", samples = 0}",
+                     &format!("{bindings}\nport = \"COM4\"\ndisplay = 2"), &format!("{bindings}\nport = \"COM5\"\ndisplay = 1"),
+                     &format!("{bindings}\ndisplay = 2"), &format!("{bindings}\nport = \"COM5\"\ndisplay = 2\nserial = {{baud_rate = 0}}")] {
+            assert!(matches!(load_text("bad", text), Err(Error::Invalid(..))), "{text}");
+        }
+        load_text("good", &format!("{bindings}\nport = \"COM5\"\ndisplay = 2")).unwrap();
+    }
+
+    fn with_profiles(profiles: &str) -> Result<Config, String> {
+        let text = format!("[[autorotate.bindings]]\nport = \"COM4\"\ndisplay = 1\n{profiles}\n");
+        let config: Config = toml::from_str(&text).map_err(|e| e.to_string())?;
+        config.validate().map(|_| config)
+    }
+
+    #[test]
+    fn applies_serial_overrides() {
+        let serial = SerialConfig {baud_rate: 115200, rts: Some(true), protocol: Protocol::Framed, ..SerialConfig::default()};
+        assert!(SerialOverrides::default().is_empty());
+        assert_eq!(SerialOverrides::default().apply(&serial), serial);
+
+        let overrides = SerialOverrides {
+            baud_rate: Some(9600), data_bits: Some(7), parity: Some(Parity::Odd), stop_bits: Some(2), flow_control: Some(FlowControl::Hardware),
+            dtr: Some(false), rts: Some(false), read_timeout_ms: Some(50), ack_timeout_ms: Some(500), protocol: Some(Protocol::Legacy)
+        };
+        let expected = SerialConfig {
+            baud_rate: 9600, data_bits: 7, parity: Parity::Odd, stop_bits: 2, flow_control: FlowControl::Hardware,
+            dtr: false, rts: Some(false), read_timeout_ms: 50, ack_timeout_ms: 500, protocol: Protocol::Legacy
+        };
+        assert_eq!(overrides.apply(&serial), expected);
+        // only what's set
+        let overrides = SerialOverrides {baud_rate: Some(57600), ..SerialOverrides::default()};
+        assert_eq!(overrides.apply(&serial), SerialConfig {baud_rate: 57600, ..serial.clone()});
+
+        // and what validate() goes by
+        let mut config = Config {serial: serial.clone(), ..Config::default()};
+        assert_eq!(config.serial_for(&config.autorotate.bindings[0]), serial);
+        config.autorotate.bindings[0].serial.data_bits = Some(9);
+        assert_eq!(config.validate(), Err("autorotate.bindings[0].serial.data_bits must be between 5 and 8, got 9.".to_string()));
+    }
+
+    #[test]
+    fn validates_profiles() {
+        let config = with_profiles("profiles.portrait = {resolution = [1440, 2560], refresh_hz = 60, scale_percent = 125, primary = true}").unwrap();
+        let profile = Profile {resolution: Some((1440, 2560)), refresh_hz: Some(60), scale_percent: Some(125), primary: Some(true)};
+        assert_eq!(config.autorotate.bindings[0].profiles.get(Orientation::Portrait), Some(&profile));
+
+        assert!(with_profiles("profiles.upside-down = {refresh_hz = 60}").is_err());
+        for bad in ["resolution = [0, 1080]", "resolution = [1080, 1920]", "refresh_hz = 0", "scale_percent = 90", "scale_percent = 130",
+                    "scale_percent = 600"] {
+            let err = with_profiles(&format!("profiles.landscape = {{{bad}}}")).unwrap_err();
+            assert!(err.starts_with("autorotate.bindings[0].profiles.landscape."), "{bad}: {err}");
+        }
+    }
+
+    #[test]
+    fn only_one_primary() {
+        let mut config = with_profiles("profiles.landscape = {primary = true}\nprofiles.portrait = {primary = true}").unwrap();
+        let mut other = config.autorotate.bindings[0].clone();
+        other.port = "COM5".to_string();
+        other.display = 2;
+        other.profiles.landscape = None;
+        config.autorotate.bindings.push(other.clone());
+        assert!(config.validate().is_err());
+        other.profiles.portrait = Some(Profile {primary: Some(false), ..Profile::default()});
+        config.autorotate.bindings[1] = other;
+        config.validate().unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use std::{io::{self, Read, Write}, sync::{Arc, mpsc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+use chrono::Local;
+use crossterm::{event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, terminal::{self, ClearType}, queue};
+use everythingdoer::{protocol::{Request, Response, Mode, Frame, FrameDecoder, STX}, capture::Event, config::{SerialConfig, Protocol}, error};
+use crate::{open_port, describe_payload, CAPTURE};
+
+// ASCII control codes, indexed by value
+const CONTROL_NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR", "SO", "SI",
+    "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC", "FS", "GS", "RS", "US"
+];
+
+const HELP: &str = "\
+Type bytes separated by spaces and press enter to send them:
+  control code names (SYN, ENQ, DC1, ..., HELLO is SOH), hex (0x2d) or text (\"abc\")
+:framed sends each line as one frame, :legacy as plain bytes (:mode shows which)
+Pushes get ACKed automatically, framed or not. :help shows this, esc or ctrl+c quits.";
+
+// The port gets read raw rather than through a Client, so everything the firmware sends shows up, including what the
+// protocol would've thrown away. Frames get decoded on top of that.
+// true if it was quit rather than the port failing
+pub fn run(port: &str, serial: &SerialConfig) -> bool {
+    let (mut sp, mut input) = match open_port(port, serial) {
+        Ok(v) => v,
+        Err(e) => {
+            error!(Serial, "{e}");
+            return false;
+        }
+    };
+
+    if let Err(e) = terminal::enable_raw_mode() {
+        error!(Serial, "Couldn't put the terminal in raw mode: {e}");
+        return false;
+    }
+
+    let closed = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let reader = {
+        let closed = closed.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while !closed.load(Ordering::Relaxed) {
+                match input.read(&mut buf) {
+                    Ok(0) => {
+                        _=tx.send(Err(io::ErrorKind::UnexpectedEof.into()));
+                        break;
+                    }
+                    Ok(n) => _=tx.send(Ok(buf[..n].to_vec())),
+                    Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => (),
+                    Err(e) => {
+                        _=tx.send(Err(e));
+                        break;
+                    }
+                }
+            }
+        })
+    };
+
+    let mut console = Console {
+        port: port.to_string(),
+        mode: if serial.protocol == Protocol::Framed {Mode::Framed} else {Mode::Legacy},
+        seq: 0,
+        line: String::new(),
+        history: Vec::new(),
+        history_pos: 0,
+        decoder: FrameDecoder::default(),
+        last_received: Instant::now()
+    };
+    console.record(Event::Mode(console.mode));
+    console.print(&format!("Connected to {port}, sending {}.\n{HELP}", console.mode));
+
+    let ret = loop {
+        match rx.recv_timeout(Duration::from_millis(20)) {
+            Ok(Ok(bytes)) => if let Err(e) = console.received(&mut sp, &bytes) {break Err(e)}
+            Ok(Err(e)) => break Err(e),
+            Err(mpsc::RecvTimeoutError::Timeout) => console.quiet(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        match console.keys(&mut sp) {
+            Ok(true) => (),
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e)
+        }
+    };
+
+    closed.store(true, Ordering::Relaxed);
+    _=reader.join();
+    _=terminal::disable_raw_mode();
+    println!();
+    match ret {
+        Ok(()) => true,
+        Err(e) => {
+            error!(Serial, "`{port}`: {e}");
+            false
+        }
+    }
+}
+
+struct Console {
+    port: String,
+    mode: Mode, // how typed lines get sent
+    seq: u8,
+    line: String,
+    history: Vec<String>,
+    history_pos: usize,
+    decoder: FrameDecoder,
+    last_received: Instant
+}
+
+impl Console {
+    fn received(&mut self, sp: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+        self.record(Event::Received(bytes.to_vec()));
+        self.last_received = Instant::now();
+        self.print(&format!("< {}", describe_bytes(bytes)));
+
+        for &b in bytes {
+            let idle = self.decoder.is_idle();
+            self.decoder.push(b);
+            // the firmware keeps resending a push until it's ACKed
+            if idle && b != STX { // already shown above
+                if let Response::Orientation(_) = Response::decode(b) {
+                    self.write(sp, &Request::Ack.encode())?;
+                    self.print("> ACK (automatic)");
+                }
+                continue;
+            }
+            while let Some(Frame {seq, payload}) = self.decoder.pop() {
+                self.print(&format!("< frame {seq}: {}", describe_payload(false, &payload)));
+                if let Some(&b) = payload.first() {
+                    if let Response::Orientation(_) = Response::decode(b) {
+                        self.write(sp, &Frame {seq, payload: Request::Ack.encode()}.encode())?;
+                        self.print(&format!("> frame {seq}: ACK (automatic)"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the same thing the client's reader does on a read timeout
+    fn quiet(&mut self) {
+        if self.decoder.is_idle() || self.last_received.elapsed() < Duration::from_millis(100) {return}
+        let discarded = self.decoder.discarded;
+        self.decoder.flush();
+        self.print(&format!("< ({} bytes weren't part of a valid frame)", self.decoder.discarded - discarded));
+    }
+
+    // false to quit
+    fn keys(&mut self, sp: &mut impl Write) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            let TermEvent::Key(KeyEvent {code, modifiers, kind, ..}) = event::read()? else {continue};
+            if kind == KeyEventKind::Release {continue}
+            match code {
+                KeyCode::Esc => return Ok(false),
+                KeyCode::Char('c' | 'd') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+                KeyCode::Char(c) => self.line.push(c),
+                KeyCode::Backspace => _=self.line.pop(),
+                KeyCode::Up if self.history_pos > 0 => {
+                    self.history_pos -= 1;
+                    self.line = self.history[self.history_pos].clone();
+                }
+                KeyCode::Down if self.history_pos < self.history.len() => {
+                    self.history_pos += 1;
+                    self.line = self.history.get(self.history_pos).cloned().unwrap_or_default();
+                }
+                KeyCode::Enter => {
+                    let line = std::mem::take(&mut self.line);
+                    if !line.trim().is_empty() {
+                        self.history.push(line.clone());
+                        self.history_pos = self.history.len();
+                    }
+                    if !self.enter(sp, line.trim())? {return Ok(false)}
+                }
+                _ => ()
+            }
+            self.prompt();
+        }
+        Ok(true)
+    }
+
+    // false to quit
+    fn enter(&mut self, sp: &mut impl Write, line: &str) -> io::Result<bool> {
+        match line {
+            "" => (),
+            ":quit" | ":q" => return Ok(false),
+            ":help" => self.print(HELP),
+            ":mode" => self.print(&format!("Sending {}.", self.mode)),
+            ":framed" | ":legacy" => {
+                self.mode = if line == ":framed" {Mode::Framed} else {Mode::Legacy};
+                self.record(Event::Mode(self.mode));
+                self.print(&format!("Sending {}.", self.mode));
+            }
+            _ => match parse_bytes(line) {
+                Ok(payload) if self.mode == Mode::Framed => {
+                    self.seq = self.seq.wrapping_add(1);
+                    self.write(sp, &Frame {seq: self.seq, payload: payload.clone()}.encode())?;
+                    self.print(&format!("> frame {}: {}", self.seq, describe_payload(true, &payload)));
+                }
+                Ok(bytes) => {
+                    self.write(sp, &bytes)?;
+                    self.print(&format!("> {}", describe_bytes(&bytes)));
+                }
+                Err(e) => self.print(&e)
+            }
+        }
+        Ok(true)
+    }
+
+    fn write(&self, sp: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+        self.record(Event::Sent(bytes.to_vec()));
+        sp.write_all(bytes)?;
+        sp.flush()
+    }
+
+    fn record(&self, event: Event) {
+        if let Some(capture) = CAPTURE.get() {capture.record(&self.port, event)}
+    }
+
+    // Prints above the line being typed. Raw mode doesn't turn \n into \r\n.
+    fn print(&self, text: &str) {
+        let mut stdoutl = io::stdout().lock();
+        _=queue!(stdoutl, crossterm::cursor::MoveToColumn(0), terminal::Clear(ClearType::CurrentLine));
+        let time = Local::now().format("%H:%M:%S%.3f");
+        for line in text.lines() {
+            _=write!(stdoutl, "{time} {line}\r\n");
+        }
+        drop(stdoutl);
+        self.prompt();
+    }
+
+    fn prompt(&self) {
+        let mut stdoutl = io::stdout().lock();
+        _=queue!(stdoutl, crossterm::cursor::MoveToColumn(0), terminal::Clear(ClearType::CurrentLine));
+        _=write!(stdoutl, "{}> {}", self.mode, self.line);
+        _=stdoutl.flush();
+    }
+}
+
+// e.g. SYN "hello" CR LF 0xff
+fn describe_bytes(bytes: &[u8]) -> String {
+    let mut ret = Vec::new();
+    let mut text = String::new();
+    for &b in bytes {
+        if b.is_ascii_graphic() || b == b' ' {
+            text.push(b as char);
+            continue;
+        }
+        if !text.is_empty() {ret.push(format!("{:?}", std::mem::take(&mut text)))}
+        ret.push(match CONTROL_NAMES.get(b as usize) {
+            Some(name) => name.to_string(),
+            None => format!("{b:#04x}")
+        });
+    }
+    if !text.is_empty() {ret.push(format!("{text:?}"))}
+    ret.join(" ")
+}
+
+// The reverse of describe_bytes(), plus HELLO and 0x2d style hex.
+fn parse_bytes(line: &str) -> Result<Vec<u8>, String> {
+    let mut ret = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').ok_or("Missing a closing quote.")?;
+            ret.extend_from_slice(&quoted.as_bytes()[..end]);
+            rest = quoted[end + 1..].trim_start();
+            continue;
+        }
+
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = rest[end..].trim_start();
+
+        let upper = token.to_ascii_uppercase();
+        let b = if upper == "HELLO" {
+            Request::Hello.encode()[0]
+        } else if let Some(i) = CONTROL_NAMES.iter().position(|&name| name == upper) {
+            i as u8
+        } else if let Some(hex) = upper.strip_prefix("0X") {
+            u8::from_str_radix(hex, 16).map_err(|_| format!("{token:?} isn't a byte."))?
+        } else {
+            return Err(format!("Don't know what {token:?} is, :help lists what can be sent."));
+        };
+        ret.push(b);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bytes() {
+        assert_eq!(parse_bytes("SYN enq hello 0x2d 0XFF"), Ok(vec![0x16, 0x05, 0x01, 0x2d, 0xff]));
+        assert_eq!(parse_bytes("  \"a b\"DC1 \"\" \"c\"  "), Ok(b"a b\x11c".to_vec()));
+        assert_eq!(parse_bytes(""), Ok(vec![]));
+        for bad in ["\"abc", "0x100", "0x", "0xzz", "SYNC", "abc"] {
+            assert!(parse_bytes(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn describes_bytes() {
+        assert_eq!(describe_bytes(b"\x16hello world\r\n\xff"), "SYN \"hello world\" CR LF 0xff");
+        assert_eq!(describe_bytes(b"\x7f\x02"), "0x7f STX");
+        assert_eq!(describe_bytes(b""), "");
+        // parses back, apart from quotes and backslashes, which come out escaped
+        for bytes in [&b"\x16hello world\r\n\xff"[..], b"\x01\x02\x03", b"abc\x1f", &(0..=255).filter(|&b| b != b'"' && b != b'\\').collect::<Vec<u8>>()] {
+            assert_eq!(parse_bytes(&describe_bytes(bytes)).as_deref(), Ok(bytes), "{bytes:02x?}");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::orientation::Orientation;
+use super::{DisplayBackend, Output, Profile, Error, swaps_resolution};
+
+#[derive(Clone, Debug)]
+pub struct MockOutput {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub refresh_hz: u32,
+    pub scale_percent: u32,
+    pub primary: bool,
+    pub orientation: Orientation
+}
+
+// Keeps everything in memory, for running the rotation logic without a real display.
+#[derive(Clone, Debug, Default)]
+pub struct MockBackend {
+    pub outputs: Vec<MockOutput>,
+    pub changes: Vec<(u32, Orientation)>,
+    pub failing: bool // set_mode() fails without changing anything
+}
+
+impl MockBackend {
+    pub fn new() -> Self {Self::default()}
+
+    // the first one's primary
+    pub fn with_output(mut self, name: &str, width: u32, height: u32) -> Self {
+        let primary = self.outputs.is_empty();
+        self.outputs.push(MockOutput {name: name.to_string(), width, height, refresh_hz: 60, scale_percent: 100, primary, orientation: Orientation::Landscape});
+        self
+    }
+
+    fn get(&self, id: u32) -> Result<&MockOutput, Error> {
+        self.outputs.get(id as usize).ok_or(Error::NoSuchOutput(id))
+    }
+}
+
+impl DisplayBackend for MockBackend {
+    fn outputs(&self) -> Result<Vec<Output>, Error> {
+        Ok(self.outputs.iter().enumerate().map(|(i, o)| Output {id: i as u32, name: o.name.clone()}).collect())
+    }
+
+    fn orientation(&self, id: u32) -> Result<Orientation, Error> {
+        Ok(self.get(id)?.orientation)
+    }
+
+    fn resolution(&self, id: u32) -> Result<(u32, u32), Error> {
+        let o = self.get(id)?;
+        Ok((o.width, o.height))
+    }
+
+    fn set_mode(&mut self, id: u32, ori: Orientation, profile: &Profile) -> Result<(), Error> {
+        self.get(id)?;
+        if self.failing {return Err(Error::Failed("Mock failure.".to_string()))}
+        if profile.primary == Some(true) {
+            for o in &mut self.outputs {o.primary = false}
+        }
+        let o = &mut self.outputs[id as usize];
+        match profile.resolution {
+            Some((w, h)) => (o.width, o.height) = (w, h),
+            None if swaps_resolution(o.orientation, ori) => std::mem::swap(&mut o.width, &mut o.height),
+            None => ()
+        }
+        if let Some(hz) = profile.refresh_hz {o.refresh_hz = hz}
+        if let Some(scale) = profile.scale_percent {o.scale_percent = scale}
+        if profile.primary == Some(true) {o.primary = true}
+        o.orientation = ori;
+        self.changes.push((id, ori));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_for_portrait() {
+        let mut display = MockBackend::new().with_output("DP-1", 1920, 1080).with_output("HDMI-1", 2560, 1440);
+        display.set_orientation(1, Orientation::Portrait).unwrap();
+        assert_eq!((display.orientation(1).unwrap(), display.resolution(1).unwrap()), (Orientation::Portrait, (1440, 2560)));
+        display.set_orientation(1, Orientation::PortraitFlipped).unwrap();
+        assert_eq!(display.resolution(1).unwrap(), (1440, 2560)); // still portrait
+        display.set_orientation(1, Orientation::LandscapeFlipped).unwrap();
+        assert_eq!(display.resolution(1).unwrap(), (2560, 1440));
+        assert_eq!(display.resolution(0).unwrap(), (1920, 1080));
+        assert_eq!(display.changes, [(1, Orientation::Portrait), (1, Orientation::PortraitFlipped), (1, Orientation::LandscapeFlipped)]);
+    }
+
+    #[test]
+    fn fails_without_changing_anything() {
+        let mut display = MockBackend::new().with_output("DP-1", 1920, 1080);
+        assert!(matches!(display.set_orientation(1, Orientation::Portrait), Err(Error::NoSuchOutput(1))));
+        display.failing = true;
+        assert!(display.set_orientation(0, Orientation::Portrait).is_err());
+        assert_eq!((display.orientation(0).unwrap(), display.resolution(0).unwrap()), (Orientation::Landscape, (1920, 1080)));
+        assert!(display.changes.is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::orientation::Orientation;
+
+#[cfg(windows)]
+mod win32;
+#[cfg(windows)]
+pub use win32::Win32Backend;
+
+#[cfg(target_os = "linux")]
+mod xrandr;
+#[cfg(target_os = "linux")]
+pub use xrandr::XRandrBackend;
+
+mod mock;
+pub use mock::MockBackend;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Output {
+    pub id: u32,
+    pub name: String
+}
+
+// What else changes when a display gets rotated to an orientation, from a binding's `profiles`. Whatever's left out
+// stays as it is (the resolution gets its width & height swapped, like without a profile).
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<(u32, u32)>, // width & height in that orientation, so portrait is taller than wide
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_hz: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_percent: Option<u32>, // windows only, 100, 125, 150...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool> // only true does anything, some other display has to be made primary instead
+}
+// The scales windows' settings app offers. DisplayConfig only knows them as steps away from the recommended one.
+pub const DPI_SCALES: [u32; 12] = [100, 125, 150, 175, 200, 225, 250, 300, 350, 400, 450, 500];
+// A binding's `profiles` table, keyed like the rotate subcommand's orientations.
+#[derive(Clone, Default, Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profiles {
+    #[serde(skip_serializing_if = "Option::is_none")] pub landscape: Option<Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub portrait: Option<Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub landscape_flipped: Option<Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub portrait_flipped: Option<Profile>
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NoSuchOutput(u32),
+    Failed(String)
+}
+
+// Outputs are addressed by their index in outputs(), which is the same as the iDevNum of EnumDisplayDevicesA.
+pub trait DisplayBackend: Send {
+    fn outputs(&self) -> Result<Vec<Output>, Error>;
+    fn orientation(&self, id: u32) -> Result<Orientation, Error>;
+    fn resolution(&self, id: u32) -> Result<(u32, u32), Error>;
+    // Rotates and applies the profile in one go, where the backend can.
+    fn set_mode(&mut self, id: u32, ori: Orientation, profile: &Profile) -> Result<(), Error>;
+
+    // swaps width & height when going between landscape and portrait
+    fn set_orientation(&mut self, id: u32, ori: Orientation) -> Result<(), Error> {
+        self.set_mode(id, ori, &Profile::default())
+    }
+
+    fn find(&self, name: &str) -> Result<u32, Error> {
+        self.outputs()?.into_iter().find(|o| o.name == name).map(|o| o.id).ok_or_else(|| Error::Failed(format!("Display \"{name}\" not found.")))
+    }
+}
+
+#[cfg(windows)]
+pub fn native() -> Result<Box<dyn DisplayBackend>, Error> {
+    Ok(Box::new(Win32Backend::new()))
+}
+#[cfg(target_os = "linux")]
+pub fn native() -> Result<Box<dyn DisplayBackend>, Error> {
+    Ok(Box::new(XRandrBackend::new(None)?))
+}
+
+impl Profiles {
+    pub fn get(&self, ori: Orientation) -> Option<&Profile> {
+        match ori {
+            Orientation::Landscape        => self.landscape.as_ref(),
+            Orientation::Portrait         => self.portrait.as_ref(),
+            Orientation::LandscapeFlipped => self.landscape_flipped.as_ref(),
+            Orientation::PortraitFlipped  => self.portrait_flipped.as_ref()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        Orientation::ALL.iter().all(|&ori| self.get(ori).is_none())
+    }
+}
+
+// e.g. "1080x1920 @ 60Hz, 125%, primary", empty if it doesn't change anything
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        match (self.resolution, self.refresh_hz) {
+            (Some((w, h)), Some(hz)) => parts.push(format!("{w}x{h} @ {hz}Hz")),
+            (Some((w, h)), None) => parts.push(format!("{w}x{h}")),
+            (None, Some(hz)) => parts.push(format!("{hz}Hz")),
+            (None, None) => ()
+        }
+        if let Some(scale) = self.scale_percent {parts.push(format!("{scale}%"))}
+        if self.primary == Some(true) {parts.push("primary".to_string())}
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoSuchOutput(id) => write!(f, "Display {id} not found."),
+            Error::Failed(e) => write!(f, "{e}")
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+pub fn swaps_resolution(from: Orientation, to: Orientation) -> bool {
+    (from as u8 + to as u8)%2 == 1
+}
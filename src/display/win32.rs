@@ -0,0 +1,207 @@
+use std::{mem, ptr};
+use windows::{Win32::{Graphics::Gdi::*, Foundation::{BOOL, HWND, LUID, POINTL}, UI::WindowsAndMessaging::*}, core::PCSTR};
+use windows::Win32::Devices::Display::{
+    GetDisplayConfigBufferSizes, QueryDisplayConfig, DisplayConfigGetDeviceInfo, DisplayConfigSetDeviceInfo, QDC_ONLY_ACTIVE_PATHS,
+    DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    DISPLAYCONFIG_DEVICE_INFO_TYPE, DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME
+};
+use crate::orientation::Orientation;
+use super::{DisplayBackend, Output, Profile, Error, DPI_SCALES};
+
+// DISPLAYCONFIG_DEVICE_INFO_TYPEs that aren't in the headers, what the settings app uses for the scale
+const GET_DPI_SCALE: i32 = -3;
+const SET_DPI_SCALE: i32 = -4;
+
+#[repr(C)]
+struct DpiScaleGet {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    min: i32, // steps from the recommended scale, so -min is where 100% is
+    current: i32,
+    max: i32
+}
+#[repr(C)]
+struct DpiScaleSet {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    scale: i32
+}
+
+#[derive(Default)]
+pub struct Win32Backend;
+
+impl Win32Backend {
+    pub fn new() -> Self {Win32Backend}
+
+    fn device(id: u32) -> Result<DISPLAY_DEVICEA, Error> {
+        unsafe {
+            let mut d = DISPLAY_DEVICEA::default();
+            d.cb = mem::size_of::<DISPLAY_DEVICEA>() as u32;
+
+            if EnumDisplayDevicesA(PCSTR::null(), id, &mut d, 0) != BOOL::from(false) {Ok(d)}
+            else {Err(Error::NoSuchOutput(id))}
+        }
+    }
+
+    fn settings(d: &DISPLAY_DEVICEA) -> Result<DEVMODEA, Error> {
+        unsafe {
+            let mut dm = DEVMODEA::default();
+            if EnumDisplaySettingsA(
+                PCSTR::from_raw(mem::transmute(&d.DeviceName)),
+                ENUM_CURRENT_SETTINGS, &mut dm
+            ) != BOOL::from(false) {Ok(dm)}
+            else {Err(Error::Failed(format!("Couldn't read settings of \"{}\".", name(&d.DeviceName))))}
+        }
+    }
+
+    // Only gets stored, apply() makes it happen.
+    fn change(d: &DISPLAY_DEVICEA, dm: &DEVMODEA, flags: CDS_TYPE) -> Result<(), Error> {
+        unsafe {
+            check(ChangeDisplaySettingsExA(
+                PCSTR::from_raw(mem::transmute(&d.DeviceName)),
+                dm, HWND::default(), flags | CDS_UPDATEREGISTRY | CDS_NORESET, mem::zeroed()
+            ))
+        }
+    }
+
+    // everything change() stored, all at once
+    fn apply() -> Result<(), Error> {
+        unsafe {check(ChangeDisplaySettingsExA(PCSTR::null(), ptr::null(), HWND::default(), CDS_TYPE(0), mem::zeroed()))}
+    }
+
+    // The primary display is the one at 0,0, so every other one moves over by where this one was.
+    fn move_others(id: u32, dm: &mut DEVMODEA) -> Result<(), Error> {
+        let pos = unsafe {dm.Anonymous1.Anonymous2.dmPosition};
+        let mut other = 0;
+        while let Ok(d) = Self::device(other) {
+            // detached ones don't have current settings
+            if other != id {
+                if let Ok(mut odm) = Self::settings(&d) {
+                    unsafe {
+                        let p = &mut odm.Anonymous1.Anonymous2.dmPosition;
+                        (p.x, p.y) = (p.x - pos.x, p.y - pos.y);
+                    }
+                    odm.dmFields |= DM_POSITION;
+                    Self::change(&d, &odm, CDS_TYPE(0))?;
+                }
+            }
+            other += 1;
+        }
+        unsafe {dm.Anonymous1.Anonymous2.dmPosition = POINTL {x: 0, y: 0}}
+        dm.dmFields |= DM_POSITION;
+        Ok(())
+    }
+}
+
+impl DisplayBackend for Win32Backend {
+    fn outputs(&self) -> Result<Vec<Output>, Error> {
+        let mut ret = Vec::new();
+        while let Ok(d) = Self::device(ret.len() as u32) {
+            ret.push(Output {id: ret.len() as u32, name: name(&d.DeviceString)});
+        }
+        Ok(ret)
+    }
+
+    fn orientation(&self, id: u32) -> Result<Orientation, Error> {
+        let dm = Self::settings(&Self::device(id)?)?;
+        let ori = unsafe {dm.Anonymous1.Anonymous2.dmDisplayOrientation};
+        Orientation::from_u8(ori as u8).ok_or_else(|| Error::Failed(format!("Unknown orientation {ori}.")))
+    }
+
+    fn resolution(&self, id: u32) -> Result<(u32, u32), Error> {
+        let dm = Self::settings(&Self::device(id)?)?;
+        Ok((dm.dmPelsWidth, dm.dmPelsHeight))
+    }
+
+    fn set_mode(&mut self, id: u32, ori: Orientation, profile: &Profile) -> Result<(), Error> {
+        let d = Self::device(id)?;
+        let mut dm = Self::settings(&d)?;
+
+        unsafe {
+            match profile.resolution {
+                Some((w, h)) => (dm.dmPelsWidth, dm.dmPelsHeight) = (w, h),
+                None if (dm.Anonymous1.Anonymous2.dmDisplayOrientation + ori as u32)%2==1 => mem::swap(&mut dm.dmPelsWidth, &mut dm.dmPelsHeight),
+                None => ()
+            }
+            dm.Anonymous1.Anonymous2.dmDisplayOrientation = ori as u32;
+        }
+        if let Some(hz) = profile.refresh_hz {
+            dm.dmDisplayFrequency = hz;
+            dm.dmFields |= DM_DISPLAYFREQUENCY;
+        }
+        let mut flags = CDS_TYPE(0);
+        if profile.primary == Some(true) {
+            Self::move_others(id, &mut dm)?;
+            flags = CDS_SET_PRIMARY;
+        }
+        Self::change(&d, &dm, flags)?;
+        Self::apply()?;
+
+        // the source stays the same through mode changes, so this can come after
+        match profile.scale_percent {
+            Some(scale) => set_scale(&d, scale),
+            None => Ok(())
+        }
+    }
+}
+
+fn set_scale(d: &DISPLAY_DEVICEA, percent: u32) -> Result<(), Error> {
+    let step = DPI_SCALES.iter().position(|&s| s == percent)
+        .ok_or_else(|| Error::Failed(format!("Windows doesn't have a {percent}% scale, only {DPI_SCALES:?}.")))? as i32;
+    let (adapter_id, id) = source(d)?;
+    unsafe {
+        let mut get = DpiScaleGet {header: header(GET_DPI_SCALE, mem::size_of::<DpiScaleGet>(), adapter_id, id), min: 0, current: 0, max: 0};
+        check_config(DisplayConfigGetDeviceInfo(&mut get.header))?;
+        let scale = step + get.min;
+        if scale == get.current {return Ok(())}
+        if scale > get.max {
+            return Err(Error::Failed(format!("{percent}% is more than \"{}\" goes up to.", name(&d.DeviceName))));
+        }
+        let set = DpiScaleSet {header: header(SET_DPI_SCALE, mem::size_of::<DpiScaleSet>(), adapter_id, id), scale};
+        check_config(DisplayConfigSetDeviceInfo(&set.header))
+    }
+}
+
+// The DisplayConfig adapter & source id of a GDI display (\\.\DISPLAY1).
+fn source(d: &DISPLAY_DEVICEA) -> Result<(LUID, u32), Error> {
+    let gdi_name = name(&d.DeviceName);
+    unsafe {
+        let (mut path_count, mut mode_count) = (0, 0);
+        check_config(GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count))?;
+        let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+        let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+        check_config(QueryDisplayConfig(QDC_ONLY_ACTIVE_PATHS, &mut path_count, paths.as_mut_ptr(), &mut mode_count, modes.as_mut_ptr(), ptr::null_mut()))?;
+
+        for path in &paths[..path_count as usize] {
+            let (adapter_id, id) = (path.sourceInfo.adapterId, path.sourceInfo.id);
+            let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+                header: header(DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME.0, mem::size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>(), adapter_id, id),
+                ..Default::default()
+            };
+            if DisplayConfigGetDeviceInfo(&mut source_name.header) == 0
+            && String::from_utf16_lossy(source_name.viewGdiDeviceName.split(|&c| c == 0).next().unwrap()) == gdi_name {
+                return Ok((adapter_id, id));
+            }
+        }
+    }
+    Err(Error::Failed(format!("Couldn't find \"{gdi_name}\" in the display config.")))
+}
+
+fn header(kind: i32, size: usize, adapter_id: LUID, id: u32) -> DISPLAYCONFIG_DEVICE_INFO_HEADER {
+    DISPLAYCONFIG_DEVICE_INFO_HEADER {r#type: DISPLAYCONFIG_DEVICE_INFO_TYPE(kind), size: size as u32, adapterId: adapter_id, id}
+}
+
+fn check(ret: DISP_CHANGE) -> Result<(), Error> {
+    if ret == DISP_CHANGE_SUCCESSFUL {Ok(())} else {
+        let DISP_CHANGE(i) = ret;
+        Err(Error::Failed(format!("DISP_CHANGE({i})")))
+    }
+}
+
+// DisplayConfig* return a win32 error code
+fn check_config(ret: i32) -> Result<(), Error> {
+    if ret == 0 {Ok(())} else {Err(Error::Failed(format!("DisplayConfig error {ret}.")))}
+}
+
+fn name<const N: usize, C>(chars: &[C; N]) -> String {
+    let bytes = unsafe {std::slice::from_raw_parts(chars.as_ptr() as *const u8, N * mem::size_of::<C>())};
+    String::from_utf8_lossy(bytes.split(|&b| b == 0).next().unwrap()).into_owned()
+}
@@ -0,0 +1,179 @@
+use std::fmt::Display;
+use x11rb::{connection::Connection, rust_connection::RustConnection, protocol::{randr::{self, ConnectionExt as _}, xproto::{self, ConnectionExt as _}}};
+use crate::orientation::Orientation;
+use super::{DisplayBackend, Output, Profile, Error};
+
+// RR_Rotate_* from Xrandr.h. RandR rotates counterclockwise, so Portrait (DMDO_90) is "xrandr --rotate left".
+const RR_ROTATE_0:   u16 = 1;
+const RR_ROTATE_90:  u16 = 2;
+const RR_ROTATE_180: u16 = 4;
+const RR_ROTATE_270: u16 = 8;
+const RR_ROTATE_ALL: u16 = 0xf;
+
+pub struct XRandrBackend {
+    conn: RustConnection,
+    root: xproto::Window,
+    dpmm: (f64, f64) // pixels per millimeter, kept when resizing the screen
+}
+
+impl XRandrBackend {
+    // display = None uses $DISPLAY
+    pub fn new(display: Option<&str>) -> Result<Self, Error> {
+        let (conn, screen) = x11rb::connect(display).map_err(failed)?;
+        let (root, dpmm) = {
+            let s = &conn.setup().roots[screen];
+            (s.root, (
+                s.width_in_pixels as f64 / s.width_in_millimeters.max(1) as f64,
+                s.height_in_pixels as f64 / s.height_in_millimeters.max(1) as f64
+            ))
+        };
+        conn.randr_query_version(1, 3).map_err(failed)?.reply().map_err(failed)?;
+
+        Ok(XRandrBackend {conn, root, dpmm})
+    }
+
+    fn resources(&self) -> Result<randr::GetScreenResourcesCurrentReply, Error> {
+        self.conn.randr_get_screen_resources_current(self.root).map_err(failed)?.reply().map_err(failed)
+    }
+
+    fn crtc(&self, res: &randr::GetScreenResourcesCurrentReply, id: u32) -> Result<(randr::Crtc, randr::GetCrtcInfoReply), Error> {
+        let output = *res.outputs.get(id as usize).ok_or(Error::NoSuchOutput(id))?;
+        let info = self.conn.randr_get_output_info(output, res.config_timestamp).map_err(failed)?.reply().map_err(failed)?;
+        if info.crtc == 0 {
+            return Err(Error::Failed(format!("Output \"{}\" is disabled.", String::from_utf8_lossy(&info.name))));
+        }
+        let crtc = self.conn.randr_get_crtc_info(info.crtc, res.config_timestamp).map_err(failed)?.reply().map_err(failed)?;
+        Ok((info.crtc, crtc))
+    }
+
+    // keeps the dpi
+    fn set_screen_size(&self, width: u16, height: u16) -> Result<(), Error> {
+        self.conn.randr_set_screen_size(
+            self.root, width, height,
+            (width as f64 / self.dpmm.0) as u32, (height as f64 / self.dpmm.1) as u32
+        ).map_err(failed)?.check().map_err(failed)
+    }
+
+    // The output's mode with the resolution (as it is in `ori`) and refresh rate, whichever of them are given, the rest
+    // stays like `current`. Without a refresh rate, the one closest to the current one.
+    fn find_mode<'a>(&self, res: &'a randr::GetScreenResourcesCurrentReply, id: u32, ori: Orientation, profile: &Profile, current: &randr::ModeInfo) -> Result<&'a randr::ModeInfo, Error> {
+        let output = *res.outputs.get(id as usize).ok_or(Error::NoSuchOutput(id))?;
+        let info = self.conn.randr_get_output_info(output, res.config_timestamp).map_err(failed)?.reply().map_err(failed)?;
+        let (w, h) = match (profile.resolution, ori) {
+            (Some((w, h)), Orientation::Landscape | Orientation::LandscapeFlipped) => (w, h),
+            (Some((w, h)), Orientation::Portrait | Orientation::PortraitFlipped) => (h, w), // modes aren't rotated
+            (None, _) => (current.width as u32, current.height as u32)
+        };
+
+        let mut modes = res.modes.iter().filter(|m| info.modes.contains(&m.id) && m.width as u32 == w && m.height as u32 == h);
+        let mode = match profile.refresh_hz {
+            Some(hz) => modes.find(|m| refresh_rate(m).round() as u32 == hz),
+            None => modes.min_by(|a, b| (refresh_rate(a) - refresh_rate(current)).abs().total_cmp(&(refresh_rate(b) - refresh_rate(current)).abs()))
+        };
+        mode.ok_or_else(|| Error::Failed(format!(
+            "\"{}\" has no {w}x{h} mode{}.", String::from_utf8_lossy(&info.name), profile.refresh_hz.map_or(String::new(), |hz| format!(" at {hz}Hz"))
+        )))
+    }
+}
+
+impl DisplayBackend for XRandrBackend {
+    fn outputs(&self) -> Result<Vec<Output>, Error> {
+        let res = self.resources()?;
+        res.outputs.iter().enumerate().map(|(i, &o)| {
+            let info = self.conn.randr_get_output_info(o, res.config_timestamp).map_err(failed)?.reply().map_err(failed)?;
+            Ok(Output {id: i as u32, name: String::from_utf8_lossy(&info.name).into_owned()})
+        }).collect()
+    }
+
+    fn orientation(&self, id: u32) -> Result<Orientation, Error> {
+        let (_, crtc) = self.crtc(&self.resources()?, id)?;
+        match u16::from(crtc.rotation) & RR_ROTATE_ALL {
+            RR_ROTATE_0   => Ok(Orientation::Landscape),
+            RR_ROTATE_90  => Ok(Orientation::Portrait),
+            RR_ROTATE_180 => Ok(Orientation::LandscapeFlipped),
+            RR_ROTATE_270 => Ok(Orientation::PortraitFlipped),
+            r => Err(Error::Failed(format!("Unknown rotation {r:#x}.")))
+        }
+    }
+
+    fn resolution(&self, id: u32) -> Result<(u32, u32), Error> {
+        let (_, crtc) = self.crtc(&self.resources()?, id)?;
+        Ok((crtc.width as u32, crtc.height as u32))
+    }
+
+    // X has no per-output scaling, so scale_percent gets ignored.
+    fn set_mode(&mut self, id: u32, ori: Orientation, profile: &Profile) -> Result<(), Error> {
+        let res = self.resources()?;
+        let (target, crtc) = self.crtc(&res, id)?;
+
+        let mut mode = res.modes.iter().find(|m| m.id == crtc.mode).ok_or_else(|| Error::Failed("Current mode not found.".to_string()))?;
+        if profile.resolution.is_some() || profile.refresh_hz.is_some() {
+            mode = self.find_mode(&res, id, ori, profile, mode)?;
+        }
+        let (w, h) = match ori {
+            Orientation::Landscape | Orientation::LandscapeFlipped => (mode.width, mode.height),
+            Orientation::Portrait  | Orientation::PortraitFlipped  => (mode.height, mode.width)
+        };
+        let rotation = (u16::from(crtc.rotation) & !RR_ROTATE_ALL) | match ori { // keeps reflection bits
+            Orientation::Landscape        => RR_ROTATE_0,
+            Orientation::Portrait         => RR_ROTATE_90,
+            Orientation::LandscapeFlipped => RR_ROTATE_180,
+            Orientation::PortraitFlipped  => RR_ROTATE_270
+        };
+
+        // the screen has to fit every enabled crtc, with this one's width & height swapped
+        let (mut screen_w, mut screen_h) = (crtc.x as i32 + w as i32, crtc.y as i32 + h as i32);
+        for &c in res.crtcs.iter().filter(|&&c| c != target) {
+            let info = self.conn.randr_get_crtc_info(c, res.config_timestamp).map_err(failed)?.reply().map_err(failed)?;
+            if info.mode != 0 {
+                screen_w = screen_w.max(info.x as i32 + info.width as i32);
+                screen_h = screen_h.max(info.y as i32 + info.height as i32);
+            }
+        }
+
+        // to put back if it doesn't come back on
+        let screen = self.conn.get_geometry(self.root).map_err(failed)?.reply().map_err(failed)?;
+
+        // same order of operations as xrandr: disable the crtc, resize the screen, then bring the crtc back rotated
+        self.conn.grab_server().map_err(failed)?;
+        let mut dark = false;
+        let ret = (|| {
+            let reply = self.conn.randr_set_crtc_config(target, crtc.timestamp, res.config_timestamp, crtc.x, crtc.y, 0, randr::Rotation::from(RR_ROTATE_0), &[])
+                .map_err(failed)?.reply().map_err(failed)?;
+            if reply.status != randr::SetConfig::SUCCESS {
+                return Err(Error::Failed(format!("RRSetCrtcConfig: {:?}", reply.status)));
+            }
+            dark = true;
+            self.set_screen_size(screen_w as u16, screen_h as u16)?;
+
+            let reply = self.conn.randr_set_crtc_config(target, x11rb::CURRENT_TIME, res.config_timestamp, crtc.x, crtc.y, mode.id, randr::Rotation::from(rotation), &crtc.outputs)
+                .map_err(failed)?.reply().map_err(failed)?;
+            if reply.status != randr::SetConfig::SUCCESS {
+                return Err(Error::Failed(format!("RRSetCrtcConfig: {:?}", reply.status)));
+            }
+            dark = false;
+            if profile.primary == Some(true) {
+                self.conn.randr_set_output_primary(self.root, res.outputs[id as usize]).map_err(failed)?.check().map_err(failed)?;
+            }
+            Ok(())
+        })();
+        // e.g. BadValue for a screen bigger than the maximum: bring it back the way it was, the first error is what gets reported
+        if dark {
+            _=self.set_screen_size(screen.width, screen.height);
+            _=self.conn.randr_set_crtc_config(target, x11rb::CURRENT_TIME, res.config_timestamp, crtc.x, crtc.y, crtc.mode, crtc.rotation, &crtc.outputs)
+                .map_err(failed).and_then(|cookie| cookie.reply().map_err(failed));
+        }
+        self.conn.ungrab_server().map_err(failed)?;
+        self.conn.flush().map_err(failed)?;
+        ret
+    }
+}
+
+fn refresh_rate(mode: &randr::ModeInfo) -> f64 {
+    if mode.htotal == 0 || mode.vtotal == 0 {return 0.0}
+    mode.dot_clock as f64 / (mode.htotal as f64 * mode.vtotal as f64)
+}
+
+fn failed<E: Display>(e: E) -> Error {
+    Error::Failed(e.to_string())
+}
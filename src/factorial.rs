@@ -0,0 +1,47 @@
+use std::{sync::mpsc, thread};
+use num_bigint::BigUint;
+
+// Splits 1..=n between thread_count threads, on_progress gets the number of finished threads (starting at 0).
+pub fn factorial<F: FnMut(u64)>(n: u64, thread_count: u64, mut on_progress: F) -> BigUint {
+    let (tx, rx) = mpsc::sync_channel::<BigUint>(thread_count as usize);
+
+    let ops = n/thread_count;
+    for i in 0..thread_count {
+        let tx_c = tx.clone();
+        thread::spawn(move || {
+            let _i = ops*i+1;
+            let mut local_result = BigUint::from(1u8);
+            for j in _i.._i+ops {
+                local_result *= j;
+            }
+            tx_c.send(local_result).unwrap();
+        });
+    }
+    drop(tx);
+
+    let mut result = BigUint::from(1u8);
+    on_progress(0);
+    for (count, rc) in rx.iter().enumerate() {
+        result *= rc;
+        on_progress(count as u64 + 1);
+    }
+    for i in n-n%thread_count..n {
+        result *= i+1;
+    }
+    result
+}
+
+// ASCII digits
+pub fn to_decimal(n: &BigUint) -> Vec<u8> {
+    n.to_radix_be(10).into_iter().map(|c| {c+0x30}).collect()
+}
+
+// (mantissa, exponent), e.g. ("4.0238", "2567") for 1000!
+pub fn scientific(digits: &[u8]) -> (String, String) {
+    let mut mantissa = String::from(digits[0] as char);
+    if digits.len() > 1 {
+        mantissa.push('.');
+        mantissa.extend(digits[1..digits.len().min(5)].iter().map(|&c| c as char));
+    }
+    (mantissa, format!("{}", digits.len()-1))
+}
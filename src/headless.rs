@@ -0,0 +1,43 @@
+use std::{sync::{Arc, Mutex}, path::Path, process, thread};
+use everythingdoer::{display, autorotate::Device, config::Config, lock::Lock, error};
+use crate::{open_device, spawn_listener, set_autorotate, watch_config, watch_lock, reload_config, apply_config};
+
+// Autorotation without the tray: every binding gets turned on at startup, config edits still get applied.
+// Runs until the process is killed.
+pub fn run(mut config: Config, config_path: &Path) -> ! {
+    let display = match display::native() {
+        Ok(display) => Arc::new(Mutex::new(display)),
+        Err(e) => {
+            error!(Display, "Couldn't access displays: {e}");
+            process::exit(1);
+        }
+    };
+    let mut devices: Vec<Arc<Mutex<Device>>> = config.autorotate.bindings.iter().map(|binding| {
+        Arc::new(Mutex::new(open_device(binding, &config, &display)))
+    }).collect();
+
+    for device in &devices {
+        spawn_listener(device, &display);
+        set_autorotate(&mut device.lock().unwrap(), true);
+    }
+
+    let lock_path = Lock::path(config_path);
+    #[cfg(windows)]
+    if let Some(hotkey) = config.autorotate.lock_hotkey.as_ref().and_then(|h| h.parse().ok()) {
+        let lock_path = lock_path.clone();
+        crate::spawn_hotkey(hotkey, move || {
+            crate::toggle_lock(&lock_path);
+            true
+        });
+    }
+    thread::spawn(move || watch_lock(&lock_path, || true));
+
+    watch_config(config_path, || {
+        if let Some(new) = reload_config(config_path, &config) {
+            apply_config(&mut devices, &display, &new);
+            config = new;
+        }
+        true
+    });
+    unreachable!()
+}
@@ -0,0 +1,11 @@
+pub mod orientation;
+pub mod tracker;
+pub mod protocol;
+pub mod capture;
+pub mod simulator;
+pub mod display;
+pub mod autorotate;
+pub mod lock;
+pub mod config;
+pub mod factorial;
+pub mod log;
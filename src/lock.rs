@@ -0,0 +1,230 @@
+use std::{fs, io, fmt, str::FromStr, path::{Path, PathBuf}};
+use chrono::{DateTime, Local};
+
+// Keeps the monitors in whatever orientation they're in without turning autorotation off: pushes still get ACKed (the
+// firmware keeps resending them otherwise) and readings still get decided on, they just don't rotate anything.
+// It lives in a file next to the config, so `everythingdoer lock` reaches whatever's running.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Lock {
+    Off,
+    On,
+    Until(DateTime<Local>) // unlocks itself then
+}
+
+impl Lock {
+    // ~/.config/everythingdoer/lock for the default config
+    pub fn path(config_path: &Path) -> PathBuf {
+        config_path.with_file_name("lock")
+    }
+
+    // A missing file is Off, so is one that's expired.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Lock::Off),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn save(self, path: &Path) -> io::Result<()> {
+        match self {
+            Lock::Off => match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(())
+            }
+            Lock::On => fs::write(path, "locked\n"),
+            Lock::Until(until) => fs::write(path, format!("locked until {}\n", until.to_rfc3339()))
+        }
+    }
+
+    pub fn is_locked(self, now: DateTime<Local>) -> bool {
+        match self {
+            Lock::Off => false,
+            Lock::On => true,
+            Lock::Until(until) => now < until
+        }
+    }
+
+    // Off once it's expired.
+    pub fn at(self, now: DateTime<Local>) -> Self {
+        if self.is_locked(now) {self} else {Lock::Off}
+    }
+}
+
+impl fmt::Display for Lock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lock::Off => write!(f, "unlocked"),
+            Lock::On => write!(f, "locked"),
+            Lock::Until(until) => write!(f, "locked until {}", until.format("%H:%M:%S"))
+        }
+    }
+}
+
+impl FromStr for Lock {
+    type Err = String;
+
+    // what save() writes
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" | "unlocked" => Ok(Lock::Off),
+            "locked" => Ok(Lock::On),
+            s => match s.strip_prefix("locked until ") {
+                Some(until) => DateTime::parse_from_rfc3339(until)
+                    .map(|until| Lock::Until(until.with_timezone(&Local)))
+                    .map_err(|e| format!("Bad lock expiry {until:?}: {e}")),
+                None => Err(format!("Don't know what lock {s:?} is."))
+            }
+        }
+    }
+}
+
+// e.g. 90s, 15m, 1h, 1h30m. A bare number is minutes.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let s = s.trim();
+    let too_long = || format!("{s:?} is too long.");
+    let not_a_duration = || format!("{s:?} isn't a duration, e.g. 90s, 15m or 1h30m.");
+    match s.parse::<u32>() {
+        Ok(0) => return Err(not_a_duration()),
+        Ok(minutes) => return chrono::Duration::try_minutes(minutes as i64).ok_or_else(too_long),
+        Err(_) => ()
+    }
+
+    let mut total = chrono::Duration::zero();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(|| format!("{s:?} is missing a unit (s, m or h)."))?;
+        let n: i64 = rest[..end].parse().map_err(|_| not_a_duration())?;
+        let unit = rest[end..].chars().next().unwrap(); // there's a non-digit at end
+        let part = match unit {
+            's' => chrono::Duration::try_seconds(n),
+            'm' => chrono::Duration::try_minutes(n),
+            'h' => chrono::Duration::try_hours(n),
+            _ => return Err(format!("Don't know the unit {unit:?} in {s:?}, use s, m or h."))
+        };
+        total = part.and_then(|part| total.checked_add(&part)).ok_or_else(too_long)?;
+        rest = &rest[end + unit.len_utf8()..];
+    }
+    if total <= chrono::Duration::zero() {return Err(not_a_duration())}
+    Ok(total)
+}
+
+// [autorotate] lock_hotkey, e.g. "ctrl+alt+l": a letter, digit or F1-F24 with at least one modifier.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Hotkey {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+    pub key: Key
+}
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Key {
+    Char(char), // uppercase
+    F(u8)
+}
+
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = Hotkey {ctrl: false, alt: false, shift: false, win: false, key: Key::F(0)};
+        let mut key = None;
+        for part in s.split('+').map(|p| p.trim().to_ascii_lowercase()) {
+            match part.as_str() {
+                "ctrl" | "control" => ret.ctrl = true,
+                "alt" => ret.alt = true,
+                "shift" => ret.shift = true,
+                "win" | "super" => ret.win = true,
+                _ if key.is_some() => return Err(format!("Hotkey {s:?} has more than one key.")),
+                p if p.len() == 1 && p.chars().all(|c| c.is_ascii_alphanumeric()) => key = Some(Key::Char(p.chars().next().unwrap().to_ascii_uppercase())),
+                p => match p.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=24) => key = Some(Key::F(n)),
+                    _ => return Err(format!("Don't know the key {part:?} in hotkey {s:?}."))
+                }
+            }
+        }
+        ret.key = key.ok_or_else(|| format!("Hotkey {s:?} needs a key, e.g. ctrl+alt+l."))?;
+        if !(ret.ctrl || ret.alt || ret.shift || ret.win) {
+            return Err(format!("Hotkey {s:?} needs a modifier (ctrl, alt, shift or win)."));
+        }
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (on, name) in [(self.ctrl, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+"), (self.win, "win+")] {
+            if on {write!(f, "{name}")?}
+        }
+        match self.key {
+            Key::Char(c) => write!(f, "{}", c.to_ascii_lowercase()),
+            Key::F(n) => write!(f, "f{n}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("15"), Ok(chrono::Duration::minutes(15)));
+        assert_eq!(parse_duration("90s"), Ok(chrono::Duration::seconds(90)));
+        assert_eq!(parse_duration(" 1h30m "), Ok(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("1m30s"), Ok(chrono::Duration::seconds(90)));
+        for bad in ["", "0", "0m", "5x", "5é", "é", "m", "1h30", "-5m", "9999999999999999h", "9223372036854775807s", "99999999999999999999s",
+                    "2000000000000h2000000000000h"] {
+            assert!(parse_duration(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn lock_round_trips() {
+        let until = DateTime::parse_from_rfc3339("2026-10-18T14:05:00+02:00").unwrap().with_timezone(&Local);
+        for lock in [Lock::Off, Lock::On, Lock::Until(until)] {
+            let text = match lock { // what save() writes
+                Lock::Off => "unlocked".to_string(),
+                Lock::On => "locked\n".to_string(),
+                Lock::Until(until) => format!("locked until {}\n", until.to_rfc3339())
+            };
+            assert_eq!(text.parse::<Lock>(), Ok(lock));
+        }
+        assert_eq!(Lock::Off.to_string(), "unlocked");
+        assert_eq!(Lock::On.to_string(), "locked");
+        assert_eq!(Lock::Until(until).to_string(), format!("locked until {}", until.format("%H:%M:%S")));
+        assert!("locked forever".parse::<Lock>().is_err());
+        assert!("locked until tomorrow".parse::<Lock>().is_err());
+
+        assert!(Lock::Until(until).is_locked(until - chrono::Duration::seconds(1)));
+        assert_eq!(Lock::Until(until).at(until), Lock::Off);
+        assert_eq!(Lock::On.at(until), Lock::On);
+    }
+
+    #[test]
+    fn lock_file() {
+        let path = std::env::temp_dir().join(format!("everythingdoer-lock-test-{}", std::process::id()));
+        assert_eq!(Lock::load(&path).unwrap(), Lock::Off); // missing
+        Lock::On.save(&path).unwrap();
+        assert_eq!(Lock::load(&path).unwrap(), Lock::On);
+        Lock::Off.save(&path).unwrap();
+        assert!(!path.exists());
+        Lock::Off.save(&path).unwrap(); // already gone
+    }
+
+    #[test]
+    fn hotkeys() {
+        let hotkey: Hotkey = "Ctrl+Alt+L".parse().unwrap();
+        assert_eq!(hotkey, Hotkey {ctrl: true, alt: true, shift: false, win: false, key: Key::Char('L')});
+        assert_eq!(hotkey.to_string(), "ctrl+alt+l");
+        assert_eq!("shift + win + f12".parse::<Hotkey>().unwrap().key, Key::F(12));
+        assert_eq!("super+7".parse::<Hotkey>().unwrap(), Hotkey {ctrl: false, alt: false, shift: false, win: true, key: Key::Char('7')});
+        for s in ["ctrl+alt+l", "shift+f1", "win+f24", "ctrl+shift+0"] {
+            assert_eq!(s.parse::<Hotkey>().unwrap().to_string(), s);
+        }
+        for bad in ["l", "ctrl", "ctrl+alt", "ctrl+l+k", "ctrl+f0", "ctrl+f25", "ctrl+é", "ctrl+space", "alt+"] {
+            assert!(bad.parse::<Hotkey>().is_err(), "{bad:?}");
+        }
+    }
+}
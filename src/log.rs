@@ -0,0 +1,125 @@
+use std::{fmt, fs::{File, OpenOptions}, io::{self, Write}, path::Path, sync::{Mutex, PoisonError}};
+use chrono::{DateTime, Local};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Level {Error, Warn, Info, Debug}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Target {Serial, Display, Factorial, Config, Tray}
+
+struct Logger {
+    level: Level,
+    terminal: Option<ColorChoice>,
+    file: Option<File>
+}
+
+// Every record is written while holding the lock, so lines from different threads never interleave.
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {level: Level::Info, terminal: Some(ColorChoice::Always), file: None});
+
+// terminal = None logs to the file only. The file gets appended to.
+pub fn init(level: Level, terminal: Option<ColorChoice>, file: Option<&Path>) -> io::Result<()> {
+    let file = match file {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None
+    };
+    *LOGGER.lock().unwrap_or_else(PoisonError::into_inner) = Logger {level, terminal, file};
+    Ok(())
+}
+
+// `Backticked` parts of the message are values, highlighted on the terminal. Use the macros instead of calling this.
+pub fn log(level: Level, target: Target, args: fmt::Arguments) {
+    let mut logger = LOGGER.lock().unwrap_or_else(PoisonError::into_inner);
+    if level > logger.level {return}
+
+    let msg = args.to_string();
+    let time = Local::now();
+    if let Some(choice) = logger.terminal {
+        _=write_terminal(&mut StandardStream::stdout(choice).lock(), time, level, target, &msg);
+    }
+    if let Some(ref mut file) = logger.file {
+        _=writeln!(file, "{} {level:<5} {target:<9} {}", time.format("%Y-%m-%d %H:%M:%S%.3f"), msg.replace('`', ""));
+    }
+}
+
+fn write_terminal(out: &mut impl WriteColor, time: DateTime<Local>, level: Level, target: Target, msg: &str) -> io::Result<()> {
+    out.set_color(ColorSpec::new().set_fg(Some(Color::White)))?;
+    write!(out, "{} ", time.format("%H:%M:%S%.3f"))?;
+    out.set_color(ColorSpec::new().set_fg(Some(level.color())).set_intense(true).set_bold(level <= Level::Warn))?;
+    write!(out, "{level:<5} ")?;
+    out.set_color(ColorSpec::new().set_fg(Some(Color::White)))?;
+    write!(out, "{target:<9} ")?;
+
+    for (i, part) in msg.split('`').enumerate() {
+        out.set_color(ColorSpec::new().set_fg(Some(if i % 2 == 1 {Color::Magenta} else {level.color()})).set_intense(true))?;
+        write!(out, "{part}")?;
+    }
+    out.reset()?;
+    writeln!(out)?;
+    out.flush()
+}
+
+impl Level {
+    fn color(self) -> Color {
+        match self {
+            Level::Error => Color::Red,
+            Level::Warn  => Color::Yellow,
+            Level::Info  => Color::Cyan,
+            Level::Debug => Color::Blue
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "ERROR",
+            Level::Warn  => "WARN",
+            Level::Info  => "INFO",
+            Level::Debug => "DEBUG"
+        })
+    }
+}
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn"  => Ok(Level::Warn),
+            "info"  => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("Unknown log level \"{s}\", expected error, warn, info or debug."))
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Target::Serial    => "serial",
+            Target::Display   => "display",
+            Target::Factorial => "factorial",
+            Target::Config    => "config",
+            Target::Tray      => "tray"
+        })
+    }
+}
+
+// error!(Serial, "Couldn't open `{port}`: {e}")
+#[macro_export]
+macro_rules! error {
+    ($target:ident, $($arg:tt)*) => {$crate::log::log($crate::log::Level::Error, $crate::log::Target::$target, format_args!($($arg)*))};
+}
+#[macro_export]
+macro_rules! warn {
+    ($target:ident, $($arg:tt)*) => {$crate::log::log($crate::log::Level::Warn, $crate::log::Target::$target, format_args!($($arg)*))};
+}
+#[macro_export]
+macro_rules! info {
+    ($target:ident, $($arg:tt)*) => {$crate::log::log($crate::log::Level::Info, $crate::log::Target::$target, format_args!($($arg)*))};
+}
+#[macro_export]
+macro_rules! debug {
+    ($target:ident, $($arg:tt)*) => {$crate::log::log($crate::log::Level::Debug, $crate::log::Target::$target, format_args!($($arg)*))};
+}
@@ -1,798 +1,954 @@
-use std::{io::{self, Write, Read, StdoutLock}, sync::{mpsc, Arc, Mutex}, thread, fs::File, time::Duration, mem};
-use crossterm::{execute, terminal::{enable_raw_mode, disable_raw_mode}, cursor};
-use num_bigint::{BigUint, ToBigUint};
-use serialport::SerialPort;
+use std::{io::{self, Write}, sync::{Arc, Weak, Mutex, OnceLock, mpsc::{Receiver, RecvTimeoutError}, atomic::{AtomicBool, Ordering}}, thread, fs::{self, File}, time::{Duration, Instant}, path::Path, process, collections::HashMap};
+use chrono::Local;
 use stopwatch::Stopwatch;
-use winit::{event::Event, event_loop::{ControlFlow, EventLoop}};
-use trayicon::{MenuBuilder, TrayIconBuilder, TrayIcon};
-use termcolor::*;
-use windows::{Win32::{Graphics::Gdi::*, Foundation::{BOOL, HWND}, UI::WindowsAndMessaging::*}, core::PCSTR};
-
-/* #region MACROS */
-
-static mut COLOR: ColorSpec = unsafe {const_zero::const_zero!(ColorSpec)};
-macro_rules! color { // !NOT! thread safe. This is on purpose — color!() should only be used when a lock on io::stdout() is acquired.
-    ($type:ident)       => {unsafe {COLOR.set_fg(Some(Color::$type)).set_intense(false)}};
-    ($type:ident, true) => {unsafe {COLOR.set_fg(Some(Color::$type)).set_intense(true)}};
-}
-macro_rules! clr_print {
-    ($stdout:expr, $color:ident, $($arg:tt)*) => {
-        ($stdout).set_color(color!($color, true)).unwrap();
-        print!($($arg)*)
-    };
-    ($stdout:expr, ($color:ident, true), $($arg:tt)*) => {
-        ($stdout).set_color(color!($color, true)).unwrap();
-        print!($($arg)*)
-    };
-}
-macro_rules! clr_write {
-    ($stdout:expr, $color:ident, $($arg:tt)*) => {
-        ($stdout).set_color(color!($color, true)).unwrap();
-        write!($($arg)*).unwrap();
+use serialport::{SerialPort, SerialPortType, DataBits, StopBits};
+use termcolor::ColorChoice;
+use everythingdoer::{orientation::Orientation, protocol::{self, Client, Request, Response, Mode, Frame, FrameDecoder, Firmware, Tuning, Sample, Reading, SOH, SO, SI, DLE, RS, STX}, capture::{self, Capture, Event}, display::{DisplayBackend, Profile}, autorotate::{Binding, UsbId, Device}, config::{Config, SerialConfig, Protocol}, tracker::Decider, lock::Lock, factorial, log::{self, Level}};
+use everythingdoer::{error, warn, info, debug};
+
+mod cli;
+mod console;
+mod headless;
+mod telemetry;
+#[cfg(windows)]
+mod tray;
+
+// --headless: no console to bring up.
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+// Called whenever a device disconnects or reconnects, the tray updates its tooltip.
+static CONNECTION_CHANGED: Mutex<Option<Box<dyn Fn() + Send>>> = Mutex::new(None);
+// --capture: every port that gets opened records its traffic here.
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+// The orientation lock, kept in line with its file by watch_lock().
+static LOCK: Mutex<Lock> = Mutex::new(Lock::Off);
+
+type Port = Box<dyn SerialPort>;
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+// how often a pending rotation gets checked on, see Gate
+const SETTLE_TICK: Duration = Duration::from_millis(20);
+
+fn main() {
+    /* #region STARTUP */
+    let args = cli::command().get_matches();
+    HEADLESS.store(args.contains_id("headless"), Ordering::Relaxed);
+
+    // headless output goes to the log file if there is one, otherwise plain to stdout for the journal
+    let log_file = args.get_one::<String>("log").map(Path::new);
+    let terminal = match (HEADLESS.load(Ordering::Relaxed), log_file) {
+        (false, _)   => Some(ColorChoice::Always),
+        (true, None) => Some(ColorChoice::Never),
+        (true, Some(_)) => None
     };
-    ($stdout:expr, ($color:ident, true), $($arg:tt)*) => {
-        ($stdout).set_color(color!($color, true)).unwrap();
-        write!($($arg)*).unwrap();
+    if let Err(e) = log::init(*args.get_one::<Level>("log-level").unwrap(), terminal, log_file) {
+        eprintln!("Couldn't open log \"{}\": {e}", log_file.unwrap().display());
+        process::exit(1);
+    }
+    #[cfg(windows)]
+    if HEADLESS.load(Ordering::Relaxed) {
+        _=unsafe {windows::Win32::System::Console::FreeConsole()};
+    }
+
+    if let Some(path) = args.get_one::<String>("capture") {
+        match Capture::open(Path::new(path)) {
+            Ok(capture) => _=CAPTURE.set(capture),
+            Err(e) => {
+                error!(Serial, "Couldn't open capture `{path}`: {e}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let (config, config_path) = match Config::load(args.get_one::<String>("config").map(Path::new)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!(Config, "{e}");
+            process::exit(1);
+        }
     };
-}
 
-/* #endregion */
+    if args.subcommand().is_some() {
+        process::exit(if cli::run(&args, &config, &config_path) {0} else {1});
+    }
 
-/* #region ENUMS */
+    info!(Config, "Loaded config `{}`.", config_path.display());
+    /* #endregion */
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
-#[repr(u8)]
-enum Orientation {
-    Landscape = DMDO_DEFAULT as u8,
-    Portrait  = DMDO_90 as u8,
-    LandscapeFlipped = DMDO_180 as u8,
-    PortraitFlipped = DMDO_270 as u8
+    #[cfg(windows)]
+    if !HEADLESS.load(Ordering::Relaxed) {tray::run(config, config_path)}
+    headless::run(config, &config_path) // there's no tray outside of windows
 }
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
-enum Events {
-    //CudaFactorial,
-    Factorial,
-    SerialEnum, SerialTestComms,
-    SerialQueryStatus, SerialIMURecalibrate,
-    SerialAutoRotateMonitor,
-    SerialRotateMonitor(Orientation),
-    SerialPortChanged(usize),
 
-    HideConsole, RefreshMenu,
-    Exit//, None
+
+fn open_client(port: &str, serial: &SerialConfig) -> Option<Client<Box<dyn SerialPort>>> {
+    match connect(port, serial) {
+        Ok(client) => Some(client),
+        Err(e) => {
+            show_console();
+            error!(Serial, "{e}");
+            None
+        }
+    }
 }
 
-/* #endregion */
+// open_client() without the error message, so reconnect attempts can stay quiet about it.
+fn connect(port: &str, serial: &SerialConfig) -> Result<Client<Box<dyn SerialPort>>, String> {
+    let (sp, input) = open_port(port, serial)?;
+    info!(Serial, "Opened serial port `{port}`.");
+
+    let mut client = Client::new(sp, input, serial.ack_timeout());
+    if let Some(capture) = CAPTURE.get() {client.set_capture(capture.clone(), port)}
+    match serial.protocol {
+        Protocol::Auto => match client.negotiate() {
+            Ok(mode) => info!(Serial, "`{port}` speaks the `{mode}` protocol."),
+            Err(e) => {
+                warn!(Serial, "Couldn't negotiate a protocol with `{port}`, falling back to `legacy`: {e}");
+                client.set_mode(Mode::Legacy);
+            }
+        }
+        Protocol::Legacy => client.set_mode(Mode::Legacy),
+        Protocol::Framed => client.set_mode(Mode::Framed)
+    }
+
+    // legacy firmware can't be asked, so it's trusted with every command
+    if client.mode() == Mode::Framed {
+        match client.hello() {
+            Ok(fw) => info!(Serial, "`{port}` runs `{fw}`, supporting `{}`.", fw.command_names()),
+            Err(e @ protocol::Error::Incompatible(_)) => return Err(format!("Not using `{port}`: {e}")),
+            Err(e) => warn!(Serial, "Couldn't identify the firmware on `{port}`, assuming it supports every command: {e}")
+        }
+    }
+    Ok(client)
+}
 
-/* #region CONSTANTS */
+// The port, and a clone of it to read from on another thread. Every port gets opened through here.
+fn open_port(port: &str, serial: &SerialConfig) -> Result<(Port, Port), String> {
+    let open = || -> serialport::Result<_> {
+        let mut sp = serialport::new(port, serial.baud_rate)
+            .data_bits(match serial.data_bits {5 => DataBits::Five, 6 => DataBits::Six, 7 => DataBits::Seven, _ => DataBits::Eight})
+            .parity(serial.parity.into())
+            .stop_bits(if serial.stop_bits == 2 {StopBits::Two} else {StopBits::One})
+            .flow_control(serial.flow_control.into())
+            .timeout(serial.read_timeout())
+            .open()?;
+        // ptys (the simulator) don't have these
+        _=sp.write_data_terminal_ready(serial.dtr);
+        if let Some(rts) = serial.rts {_=sp.write_request_to_send(rts)}
+        let input = sp.try_clone()?;
+        Ok((sp, input))
+    };
+    open().map_err(|e| format!("Couldn't open serial port `{port}`: {e}"))
+}
 
-const FACTORIAL_THREAD_COUNT: u64 = 32;
-const SERIAL_DEFAULT_NAME: &'static str = "COM4";
-const SERIAL_BAUD_RATE: u32 = 9600;
-const SERIAL_ACK_TIMEOUT: i64 = 10000;
-const AUTOROTATE_ID: u32 = 1;
-const AUTOROTATE_THRESHOLD_DEG: u8 = 65;
+// The display is only locked to look up its orientation, opening the port can take a while.
+fn open_device(binding: &Binding, config: &Config, display: &Mutex<Box<dyn DisplayBackend>>) -> Device {
+    let current_ori = display.lock().unwrap().orientation(binding.display).unwrap_or(Orientation::Landscape);
+    let mut device = Device::new(binding.clone(), config.serial_for(binding), config.autorotate.tuning(), config.autorotate.settle, config.autorotate.host, current_ori);
+    match binding.resolve_port() {
+        Ok(port) => {
+            device.client = open_client(&port, &device.serial);
+            device.port = port;
+        }
+        Err(e) => {
+            show_console();
+            error!(Serial, "{e}");
+        }
+    }
+    device
+}
 
-const SYN: u8 = 0x16;
-const ACK: u8 = 0x06;
-const NAK: u8 = 0x15;
-const ENQ: u8 = 0x05;
-const DC1: u8 = 0x11;
-const DC2: u8 = 0x12;
-const DC3: u8 = 0x13;
-const DC4: u8 = 0x14;
+// What the listener waits on: pushes, or with [autorotate.host] the readings it decides on.
+enum Stream {
+    Pushes(Receiver<Orientation>),
+    Readings(Receiver<Reading>)
+}
 
-/* #endregion */
+// Owns the device's connection for as long as it's in the devices list: rotates the display whenever the device pushes
+// a new orientation (or the readings say so), and reopens the port (with backoff) while it's missing or after it stopped responding.
+fn spawn_listener(device: &Arc<Mutex<Device>>, display: &Arc<Mutex<Box<dyn DisplayBackend>>>) {
+    let weak = Arc::downgrade(device);
+    let display = Arc::clone(display);
 
-fn main() {
-    /* #region STARTUP */
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    unsafe {COLOR = ColorSpec::new();}
-
-    /*clr_print!(stdout, (Magenta, true), "<——————————————————————————————————————————————————————————————————————————————————————>");
-    clr_print!(stdout, (Cyan, true), r"
-    ______                      __  __    _                 __                "); clr_print!(stdout, (Magenta, true), "███ ██ ██"); clr_print!(stdout, (Cyan, true), r"
-   / ____/   _____  _______  __/ /_/ /_  (_)___  ____ _____/ /___  ___  _____ "); clr_print!(stdout, (Magenta, true), " █  █ █ █"); clr_print!(stdout, (Cyan, true), r"
-  / __/ | | / / _ \/ ___/ / / / __/ __ \/ / __ \/ __ `/ __  / __ \/ _ \/ ___/          
- / /___ | |/ /  __/ /  / /_/ / /_/ / / / / / / / /_/ / /_/ / /_/ /  __/ /              
-/_____/ |___/\___/_/   \__, /\__/_/ /_/_/_/ /_/\__, /\__,_/\____/\___/_/               
-                      /____/                  /____/                                   
-");// */
-    clr_print!(stdout, (Magenta, true), "<——————————————————————————————————————————————————————————————————————————————————————>\n");
-    io::stdout().flush().unwrap();
-
-    let event_loop = EventLoop::with_user_event();
-    let icon = include_bytes!("icon.ico");
-
-    let serial_port = Arc::new(Mutex::new(None));
-    let mut current_ori;
-    /* #endregion */
+    thread::spawn(move || {
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        let mut next_attempt = Instant::now() + backoff;
+        while let Some(device) = weak.upgrade() {
+            let mut dev = device.lock().unwrap();
+
+            let host = dev.host.is_some();
+            let stream = dev.client.as_mut().and_then(|client| match host {
+                true => client.take_raw().map(Stream::Readings),
+                false => client.take_pushes().map(Stream::Pushes)
+            });
+            if let Some(stream) = stream {
+                backoff = RECONNECT_BACKOFF_MIN;
+                drop(dev);
+                drop(device);
+                let present = match stream {
+                    Stream::Pushes(pushes) => drain(&weak, &display, pushes, on_push),
+                    Stream::Readings(readings) => drain(&weak, &display, readings, on_reading)
+                };
+                if !present {return}
+                continue;
+            }
 
-    /* #region TASKBAR MENU SETUP */
-    macro_rules! menu {
-        () => {MenuBuilder::new()
-            /*.with(MenuItem::Item {
-                id: Events::None,
-                name: "Everythingdoer™".to_string(),
-                disabled: true,
-                icon: Some(Icon::from_buffer(icon, None, None).unwrap())
-            })*/
-            
-            .separator()
-
-            .item("Refresh menu", Events::RefreshMenu)
-            .checkable("Hide console", false, Events::HideConsole)
-            
-            .separator()
-
-            .submenu("Serial (Arduino)", {
-                let mut ret = MenuBuilder::new()
-                    .item("Print serial ports", Events::SerialEnum)
-                    .item("Test comms with selected port", Events::SerialTestComms)
-                    .item("Query selected port", Events::SerialQueryStatus)
-                    .item("Recalibrate selected port's IMU", Events::SerialIMURecalibrate)
-                    .submenu("Monitor", unsafe {
-                        let mut d = DISPLAY_DEVICEA::default();
-                        d.cb = mem::size_of::<DISPLAY_DEVICEA>() as u32;
-                        let mut dm = DEVMODEA::default();
-                        
-                        EnumDisplayDevicesA(PCSTR::null(), AUTOROTATE_ID, &mut d, 0);
-                        EnumDisplaySettingsA(PCSTR::from_raw(mem::transmute(&d.DeviceName)), ENUM_CURRENT_SETTINGS, &mut dm);
-                        current_ori = dm.Anonymous1.Anonymous2.dmDisplayOrientation;
-                        
-                        MenuBuilder::new()
-                            .checkable("Landscape",           current_ori == Orientation::Landscape as u32,        Events::SerialRotateMonitor(Orientation::Landscape))
-                            .checkable("Landscape (flipped)", current_ori == Orientation::LandscapeFlipped as u32, Events::SerialRotateMonitor(Orientation::LandscapeFlipped))
-                            .checkable("Portrait",            current_ori == Orientation::Portrait as u32,         Events::SerialRotateMonitor(Orientation::Portrait))
-                            .checkable("Portrait (flipped)",  current_ori == Orientation::PortraitFlipped as u32,  Events::SerialRotateMonitor(Orientation::PortraitFlipped))
-                            .checkable("Auto-rotate", false, Events::SerialAutoRotateMonitor)
-                    }).separator();
-                
-                let mut default_not_found = true;
-                for (i, port) in serialport::available_ports().unwrap().iter().enumerate() {
-                    let mut check = false;
-                    if port.port_name == SERIAL_DEFAULT_NAME {
-                        check  = true;
-                        default_not_found = false;
-                        match serialport::new(SERIAL_DEFAULT_NAME, SERIAL_BAUD_RATE).open() {
-                            Ok(mut sp) => {
-                                let mut stdoutl = io::stdout().lock();
-                                clr_write!(stdout, (Cyan, true), stdoutl, "Opened serial port \"{SERIAL_DEFAULT_NAME}\".\n");
-                                stdoutl.flush().unwrap();
-                                sp.set_timeout(Duration::from_millis(1000)).unwrap();
-                                sp.set_data_bits(serialport::DataBits::Eight).unwrap();
-                                sp.set_flow_control(serialport::FlowControl::None).unwrap();
-                                sp.set_parity(serialport::Parity::None).unwrap();
-                                sp.set_stop_bits(serialport::StopBits::One).unwrap();
-                                sp.write_data_terminal_ready(true).unwrap();
-                                *serial_port.lock().unwrap() = Some(sp);
-                            }
-                            Err(e) => {
-                                winconsole::window::show(true);
-                                let mut stdoutl = io::stdout().lock();
-                                clr_write!(stdout, (Red, true), stdoutl, "Couldn't open serial port \"{SERIAL_DEFAULT_NAME}\": ");
-                                clr_write!(stdout, Red, stdoutl, "{}\n", e.to_string());
-                                stdoutl.flush().unwrap();
-                            }
-                        }
+            if dev.client.is_some() && !dev.is_connected() {
+                show_console();
+                error!(Serial, "Lost the connection to `{}`, reconnecting...", dev.port);
+                dev.client = None; //the port has to be closed before it can be reopened
+                next_attempt = Instant::now() + backoff;
+                connection_changed();
+            } else if dev.client.is_none() && Instant::now() >= next_attempt {
+                let (binding, serial) = (dev.binding.clone(), dev.serial.clone());
+                drop(dev); // opening can take a while, the tray shouldn't hang meanwhile
+                // resolved again every time, a USB device can come back as another port
+                let client = binding.resolve_port().and_then(|port| Ok((connect(&port, &serial)?, port)));
+
+                dev = device.lock().unwrap();
+                if dev.client.is_some() || dev.binding != binding {continue} // reopened by someone else meanwhile
+                match client {
+                    Ok((client, port)) => {
+                        info!(Serial, "Reconnected to `{port}`.");
+                        dev.client = Some(client);
+                        dev.port = port;
+                        resume_autorotate(&mut dev);
+                        connection_changed();
+                    }
+                    Err(e) => {
+                        debug!(Serial, "{e}, retrying in `{}s`.", backoff.as_secs());
+                        next_attempt = Instant::now() + backoff;
+                        backoff = next_backoff(backoff);
                     }
-                    ret = ret.checkable(&port.port_name, check, Events::SerialPortChanged(i));
                 }
+            }
+            drop(dev);
+            drop(device);
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+}
 
-                if default_not_found {
-                    winconsole::window::show(true);
-                    let mut stdoutl = io::stdout().lock();
-                    clr_write!(stdout, (Red, true), stdoutl, "Serial port \"{SERIAL_DEFAULT_NAME}\" not found.\n");
-                    stdoutl.flush().unwrap();
-                }
+// How long to wait after another failed reconnect: twice as long, up to RECONNECT_BACKOFF_MAX.
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(RECONNECT_BACKOFF_MAX)
+}
 
-                ret
-            })
-            .item("Factorial calc", Events::Factorial)
+// Hands everything that comes in to `f`, and rotates the monitor once the gate lets it. Until the client is replaced,
+// dropped or fails, false if the device is gone.
+fn drain<T>(weak: &Weak<Mutex<Device>>, display: &Mutex<Box<dyn DisplayBackend>>, rx: Receiver<T>, f: fn(&mut Device, T)) -> bool {
+    loop {
+        let received = match rx.recv_timeout(SETTLE_TICK) {
+            Ok(v) => Some(v),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return true
+        };
+        let Some(device) = weak.upgrade() else {return false};
+        let mut dev = device.lock().unwrap();
+        if !dev.autorotate {continue}
+        if let Some(v) = received {f(&mut dev, v)}
+
+        let dev = &mut *dev;
+        if is_locked() {
+            dev.gate.clear(); // whatever was pending when it got locked
+            continue;
+        }
+        if let Some(ori) = dev.gate.poll(dev.current_ori, Instant::now()) {
+            if rotate_monitor(&mut **display.lock().unwrap(), dev.binding.display, ori, dev.binding.profiles.get(ori), &mut dev.current_ori) {
+                dev.gate.rotated(Instant::now());
+            } else {
+                dev.gate.retry(ori, Instant::now());
+            }
+        }
+    }
+}
 
-            .separator()
+fn on_push(dev: &mut Device, ori: Orientation) {
+    if ori != dev.current_ori {
+        info!(Serial, "`{}`: received `{}` (`{ori:?}`), sent `ACK`.", dev.port, Response::Orientation(ori));
+    } else {
+        info!(Serial, "`{}`: received `{}` (`{ori:?}`), sent `ACK`, monitor already in requested orientation.", dev.port, Response::Orientation(ori));
+    }
+    want(dev, ori);
+}
 
-            .item("Exit", Events::Exit)
-        }
+// on_push(), but decided here
+fn on_reading(dev: &mut Device, reading: Reading) {
+    let Some(ref mut decider) = dev.decider else {return};
+    let calibrated = decider.is_calibrated();
+    let ori = decider.update(&reading, Instant::now());
+    if !calibrated && decider.is_calibrated() {
+        debug!(Serial, "`{}`: calibrated, `{:.1}°` is upright in `{:?}`.", dev.port, decider.tracker.offset, decider.tracker.orientation);
     }
 
-    let tray_icon = Arc::new(Mutex::new(TrayIconBuilder::new()
-        .sender_winit(unsafe {std::mem::transmute(event_loop.create_proxy())}) //it is literally the same struct shut up compiler
-        .icon_from_buffer(icon)
-        .tooltip("Everythingdoer™")
-        .on_click(Events::HideConsole)
-        .on_double_click(Events::RefreshMenu)
-        .menu(menu!()).build().unwrap()));
-    /* #endregion */
+    let Some(ori) = ori else {return};
+    if ori != dev.current_ori {
+        info!(Serial, "`{}`: tilted to `{ori:?}` ({reading}).", dev.port);
+    } else {
+        info!(Serial, "`{}`: tilted to `{ori:?}` ({reading}), monitor already in that orientation.", dev.port);
+    }
+    want(dev, ori);
+}
 
-    /* #region SERIAL LISTENER THREAD */
-    let serial_port_t = Arc::clone(&serial_port);
-    let tray_icon_t = Arc::clone(&tray_icon);
-    let mut stdout_t = StandardStream::stdout(ColorChoice::Always);
-    thread::spawn(move || loop {
-        {
-            let mut tray_lock = tray_icon_t.lock().unwrap();
-            if let Some(v) = tray_lock.get_menu_item_checkable(Events::SerialAutoRotateMonitor) {
-                if v {
-                    if let Some(ref mut port) = *serial_port_t.lock().unwrap() {
-                        /*{
-                            let mut stdoutl = io::stdout().lock();
-                            writeln!(stdoutl, "ok");
-                            stdoutl.flush().unwrap();
-                        }*/
-                        match port.bytes_to_read() {
-                            Ok(n) => if n > 0 {
-                                let mut buffer = [0u8];
-                                port.read_exact(&mut buffer).unwrap();
-        
-                                let mut stdoutl = io::stdout().lock();
-                                let (ori, str) = match buffer[0] {
-                                    DC1 => (Orientation::Landscape,        "DC1"),
-                                    DC2 => (Orientation::Portrait,         "DC2"),
-                                    DC3 => (Orientation::LandscapeFlipped, "DC3"),
-                                    DC4 => (Orientation::PortraitFlipped,  "DC4"),
-                                    _ => (Orientation::Landscape, "\0")
-                                };
-                                if str != "\0" {
-                                    clr_write!(stdout_t, (Cyan, true), stdoutl, "Received ");
-                                    clr_write!(stdout_t, (Magenta, true), stdoutl, "{str}");
-                                    clr_write!(stdout_t, (Cyan, true), stdoutl, " (");
-                                    clr_write!(stdout_t, (Magenta, true), stdoutl, "{ori:?}");
-                                    clr_write!(stdout_t, (Cyan, true), stdoutl, "), sending ");
-                                    clr_write!(stdout_t, (Magenta, true), stdoutl, "ACK");
-                                    clr_write!(stdout_t, (Cyan, true), stdoutl, "... ");
-                                
-                                    stdoutl.flush().unwrap();
-                                
-                                    if let Err(e) = port.write(&[ACK]) {
-                                        clr_write!(stdout_t, (Red, true), stdoutl, "ERRT: Couldn't write - ");
-                                        clr_write!(stdout_t, Red, stdoutl, "{}", e.to_string());
-                                    } else {
-                                        if let Err(e) = port.flush() {
-                                            clr_write!(stdout_t, (Red, true), stdoutl, "ERRT: Couldn't flush - ");
-                                            clr_write!(stdout_t, Red, stdoutl, "{}", e.to_string());
-                                        } else {
-                                            clr_write!(stdout_t, Green, stdoutl, "Success");
-                                            if ori as u32 != current_ori {
-                                                clr_write!(stdout_t, (Cyan, true), stdoutl, ", rotating monitor... ");
-                                                rotate_monitor(AUTOROTATE_ID, ori, &mut tray_lock, &mut current_ori, false);
-                                            } else {
-                                                clr_write!(stdout_t, (Cyan, true), stdoutl, ", monitor already in requested orientation.\n");
-                                            }
-                                        }
-                                    }
-                                }
-                                //else {clr_write!(stdout_t, (Cyan, true), stdoutl, "Received {}.\n", buffer[0]);}
-                                stdoutl.flush().unwrap();
-                            } /*else {
-                                let mut stdoutl = io::stdout().lock();
-                                writeln!(stdoutl, "{n}");
-                                stdoutl.flush().unwrap();
-                            }*/
-                            Err(e) => {
-                                let mut stdoutl = io::stdout().lock();
-                                clr_write!(stdout_t, (Red, true), stdoutl, "ERRT: Couldn't get bytes to read - ");
-                                clr_write!(stdout_t, Red, stdoutl, "{}\n", e.to_string());
-                                stdoutl.flush().unwrap();
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
+fn want(dev: &mut Device, ori: Orientation) {
+    if is_locked() {
+        if ori != dev.current_ori {info!(Display, "Orientation is locked, keeping display `{}` in `{:?}`.", dev.binding.display, dev.current_ori)}
+        return;
+    }
+    match dev.gate.want(ori, Instant::now()) {
+        Some(pending) if ori == dev.current_ori => info!(Serial, "`{}`: back before `{pending:?}` settled, not rotating.", dev.port),
+        _ => ()
+    }
+}
+
+// For when the tray changes the device's port or serial settings. If the port doesn't open, the listener keeps trying.
+#[cfg(windows)]
+fn reopen_device(dev: &mut Device, port: &str) {
+    dev.client = None; //the port has to be closed before it can be reopened
+    dev.port = port.to_string();
+    dev.client = open_client(port, &dev.serial);
+    resume_autorotate(dev);
+    connection_changed();
+}
+
+// A freshly opened device isn't running, so redo the DC1 handshake if autorotation was on.
+fn resume_autorotate(dev: &mut Device) {
+    if !dev.autorotate {return}
+    dev.autorotate = false;
+    if !set_autorotate(dev, true) {
+        dev.autorotate = true; //still wanted, retried after the next reconnect
+    }
+}
+
+fn connection_changed() {
+    if let Some(ref f) = *CONNECTION_CHANGED.lock().unwrap() {f()}
+}
+
+// Sends DC1 (with the current orientation & threshold, then the rest of the tuning) or DC2, true if the device ended up
+// in the requested state. With [autorotate.host], turns the readings on or off instead.
+// While disconnected it's only remembered, and sent once the device reconnects.
+fn set_autorotate(dev: &mut Device, on: bool) -> bool {
+    if dev.autorotate == on {return true}
+    dev.gate.clear();
+    let (ori, tuning) = (dev.current_ori, dev.tuning);
+    let threshold = tuning.threshold;
+    let client = match dev.client {
+        Some(ref mut client) => client,
+        None => {
+            dev.autorotate = on;
+            return true;
         }
-        thread::sleep(Duration::from_millis(10));
-    });
-    /* #endregion */
-    
-    /* #region NEW WINDOW HANDLER */
-    /*unsafe {
-        extern "system" fn hookproc() {0}
-        let hhook = SetWindowsHookExA(WINDOWS_HOOK_ID(10), Some(hookproc), 0, );
-    }*/
-    /* #endregion */
+    };
 
-    /* #region STARTUP EVENTS THREAD */
-    let proxy_t = event_loop.create_proxy();
-    thread::spawn(move || {
-        thread::sleep(Duration::from_secs(1));
-        if proxy_t.send_event(Events::SerialAutoRotateMonitor).is_ok() {
-            thread::sleep(Duration::from_secs(1));
-            _=proxy_t.send_event(Events::HideConsole);
+    if let Some(host) = dev.host {
+        let interval_ms = if on {host.interval_ms} else {0};
+        if serial_send(Request::Raw(interval_ms), || client.set_raw(interval_ms)).is_none() {return false}
+        serial_success(Response::Ack, "");
+        // calibrates on the first readings, like the firmware does when it boots
+        dev.decider = on.then(|| Decider::new(ori, tuning, host.filter));
+    } else if on {
+        match serial_send(format!("{} {}", Request::Start, Request::Settings {orientation: ori, threshold}), || client.start(ori, threshold)) {
+            Some(started) => {
+                if started {serial_success(Response::Ack, "")}
+                else       {serial_success(Response::Nak, " - autorotation was already running")}
+            }
+            None => return false
         }
-    });
-    /* #endregion */
-    
-    //MAIN LOOP
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
-
-        if let Event::UserEvent(e) = event {
-            {
-                let mut stdoutl = io::stdout().lock();
-                clr_write!(stdout, (Cyan, true), stdoutl, "Events");
-                clr_write!(stdout, (Magenta, true), stdoutl, "::");
-                clr_write!(stdout, (Cyan, true), stdoutl, "{e:?}\n");
-                stdoutl.flush().unwrap();
+        // firmware that can't be tuned gets by with just the threshold
+        if client.supports(Request::SetTuning(tuning)) {
+            match serial_send(Request::SetTuning(tuning), || client.set_tuning(tuning)) {
+                Some(true) => serial_success(Response::Ack, ""),
+                Some(false) => serial_error(Response::Nak),
+                None => ()
+            }
+        }
+    } else {
+        match serial_send(Request::Stop, || client.stop()) {
+            Some(true) => serial_success(Response::Ack, ""),
+            Some(false) => {
+                serial_error(Response::Nak);
+                return false;
             }
+            None => return false
+        }
+    }
+    dev.autorotate = on;
+    true
+}
 
-            match e {
-                Events::Exit => {
-                    let mut stdoutl = io::stdout().lock();
-                    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-                    
-                    if let Some(ref mut port) = *serial_port.lock().unwrap() {
-                        let mut tray_lock = tray_icon.lock().unwrap();
-                        if let Some(oldv) = tray_lock.get_menu_item_checkable(Events::SerialAutoRotateMonitor) {
-                            console_to_fg(&mut tray_lock);
-                            serial_send(port, &[DC2], "DC2", |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                                let mut buffer = [0u8];
-                                p.read_exact(&mut buffer).unwrap();
-            
-                                if buffer[0] == ACK {
-                                    clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                    clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                    clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                    clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                                    _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor, !oldv);
-                                } else {
-                                    clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);
-                                }
-                            });
-                        }
-                    }
-                    clr_write!(stdout, (Magenta, true), stdoutl, "<——————————————————————————————————————————————————————————————————————————————————————>");
-                    stdoutl.flush().unwrap();
-                    _=stdout.reset();
-            
-                    console_to_fg(&mut tray_icon.lock().unwrap());
-                    *control_flow = ControlFlow::Exit;
-                }
-                Events::HideConsole => {
-                    let mut tray_lock = tray_icon.lock().unwrap();
-                    if let Some(oldv) = tray_lock.get_menu_item_checkable(Events::HideConsole) {
-                        _=tray_lock.set_menu_item_checkable(Events::HideConsole, !oldv);
+// Sends new tuning to a running device without restarting autorotation. Firmware without SI only gets the threshold,
+// through a restart. Otherwise it's only remembered, and sent along when autorotation starts.
+fn set_tuning(dev: &mut Device, tuning: Tuning) -> bool {
+    let old = std::mem::replace(&mut dev.tuning, tuning);
+    if old == tuning || !dev.autorotate {return true}
+    if let Some(ref mut decider) = dev.decider {
+        decider.tracker.tuning = tuning; // nothing to send
+        return true;
+    }
+    let client = match dev.client {
+        Some(ref mut client) => client,
+        None => return true
+    };
 
-                        if oldv {winconsole::window::show(true);}
-                        else    {winconsole::window::hide();}
-                    }
-                }
-                Events::RefreshMenu => {
-                    let mut tray_lock = tray_icon.lock().unwrap();
-                    if let Err(e) = tray_lock.set_menu(&menu!()) {
-                        console_to_fg(&mut tray_lock);
-                        let mut stdoutl = io::stdout().lock();
-                        clr_write!(stdout, (Red, true), stdoutl, "Couldn't refresh: ");
-                        clr_write!(stdout, Red, stdoutl, "{}\n", e.to_string());
-                        stdoutl.flush().unwrap();
-                    }
-                }
+    if client.supports(Request::SetTuning(tuning)) {
+        return match serial_send(Request::SetTuning(tuning), || client.set_tuning(tuning)) {
+            Some(true) => {
+                serial_success(Response::Ack, "");
+                true
+            }
+            Some(false) => {
+                serial_error(Response::Nak);
+                false
+            }
+            None => false
+        };
+    }
+    if old.threshold == tuning.threshold {return true}
+    // the threshold only gets sent in the DC1 handshake, so restart autorotation to resend it
+    set_autorotate(dev, false) && set_autorotate(dev, true)
+}
 
-                Events::SerialRotateMonitor(ori) => rotate_monitor(AUTOROTATE_ID, ori, &mut tray_icon.lock().unwrap(), &mut current_ori, true),
-                Events::SerialAutoRotateMonitor => {
-                    if let Some(ref mut port) = *serial_port.lock().unwrap() {
-                        let mut tray_lock = tray_icon.lock().unwrap();
-                        if let Some(oldv) = tray_lock.get_menu_item_checkable(Events::SerialAutoRotateMonitor) {
-                            console_to_fg(&mut tray_lock);
-                            serial_send(port, &[if oldv {DC2} else {DC1}], if oldv {"DC2"} else {"DC1"}, |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                                let mut buffer = [0u8];
-                                p.read_exact(&mut buffer).unwrap();
-            
-                                match buffer[0] {
-                                    ACK => {
-                                        clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                        clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                        clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                        clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                                        _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor, !oldv);
-
-                                        for &o in &[Orientation::Landscape, Orientation::Portrait, Orientation::LandscapeFlipped, Orientation::PortraitFlipped] {
-                                            _=tray_lock.set_menu_item_checkable(Events::SerialRotateMonitor(o), (o as u32)==current_ori);
-                                        }
-                                    }
-                                    NAK => {
-                                        if oldv {clr_write!(stdout, (Red, true), stdoutl, "ERR: NAK received.\n");} else {
-                                            clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                            clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                            clr_write!(stdout, (Magenta, true), stdoutl, "NAK");
-                                            clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                                            _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor, !oldv);
-    
-                                            for &o in &[Orientation::Landscape, Orientation::Portrait, Orientation::LandscapeFlipped, Orientation::PortraitFlipped] {
-                                                _=tray_lock.set_menu_item_checkable(Events::SerialRotateMonitor(o), false);
-                                            }
-                                        }
-                                    } 
-                                    ENQ => {
-                                        clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                        clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                        clr_write!(stdout, (Magenta, true), stdoutl, "ENQ");
-                                        clr_write!(stdout, (Cyan, true), stdoutl, " received, sending current orientation... ");
-
-                                        serial_send(p, &[current_ori as u8, AUTOROTATE_THRESHOLD_DEG], &format!("[{current_ori:?}, {AUTOROTATE_THRESHOLD_DEG}]"), |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                                            p.read_exact(&mut buffer).unwrap();
-                                            if buffer[0] == ACK {
-                                                clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                                clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                                clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                                clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                                                _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor, !oldv);
-
-                                                for &o in &[Orientation::Landscape, Orientation::Portrait, Orientation::LandscapeFlipped, Orientation::PortraitFlipped] {
-                                                    _=tray_lock.set_menu_item_checkable(Events::SerialRotateMonitor(o), false);
-                                                }
-                                            } else {
-                                                clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);
-                                            }
-                                        });
-                                    }
-                                    
-                                    _ => {clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);}
-                                }
-                            });
-                        }
-                    }
-                }
-                Events::SerialEnum => {
-                    console_to_fg(&mut tray_icon.lock().unwrap());
-                    for port in serialport::available_ports().unwrap() {
-                        let mut stdoutl = io::stdout().lock();
-                        clr_write!(stdout, (Cyan, true), stdoutl, "{}", port.port_name);
-                        clr_write!(stdout, (Magenta, true), stdoutl, ": ");
-                        clr_write!(stdout, (Cyan, true), stdoutl, "{:?}\n", port.port_type);
-                        stdoutl.flush().unwrap();
-                    }
-                }
-                Events::SerialTestComms => {
-                    if let Some(ref mut port) = *serial_port.lock().unwrap() {
-                        console_to_fg(&mut tray_icon.lock().unwrap());
-                        serial_send(port, &[SYN], "SYN", |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                            let mut buffer = [0u8];
-                            p.read_exact(&mut buffer).unwrap();
-    
-                            if buffer[0] == ACK {
-                                clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                            } else {
-                                clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);
-                            }
-                        });
-                    }
-                }
-                Events::SerialQueryStatus => {
-                    if let Some(ref mut port) = *serial_port.lock().unwrap() {
-                        console_to_fg(&mut tray_icon.lock().unwrap());
-                        serial_send(port, &[SYN], "ENQ", |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                            let mut buffer = [0u8];
-                            p.read_exact(&mut buffer).unwrap();
-    
-                            match buffer[0] {
-                                ACK => {
-                                    clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, ": received ");
-                                    clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, " - autorotation is ");
-                                    clr_write!(stdout, Green, stdoutl, "running");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, ".\n");
-                                }
-                                NAK => {
-                                    clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, ": received ");
-                                    clr_write!(stdout, (Magenta, true), stdoutl, "NAK");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, " - autorotation is ");
-                                    clr_write!(stdout, (Red,     true), stdoutl, "not running");
-                                    clr_write!(stdout, (Cyan,    true), stdoutl, ".\n");
-                                }
-                                _ => {clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);}
-                            }
-                        });
-                    }
-                }
-                Events::SerialIMURecalibrate => {
-                    if let Some(ref mut port) = *serial_port.lock().unwrap() {
-                        console_to_fg(&mut tray_icon.lock().unwrap());
-                        serial_send(port, &[SYN], "DC3", |p: &mut Box<dyn SerialPort>, stdoutl: &mut StdoutLock| {
-                            let mut buffer = [0u8];
-                            p.read_exact(&mut buffer).unwrap();
-    
-                            if buffer[0] == ACK {
-                                clr_write!(stdout, Green, stdoutl, "\nSuccess");
-                                clr_write!(stdout, (Cyan, true), stdoutl, ": ");
-                                clr_write!(stdout, (Magenta, true), stdoutl, "ACK");
-                                clr_write!(stdout, (Cyan, true), stdoutl, " received.\n");
-                            } else {
-                                clr_write!(stdout, (Red, true), stdoutl, "ERR: Received non-ACK code \"{:#04x}\".\n", buffer[0]);
-                            }
-                        });
-                    }
-                }
-                Events::SerialPortChanged(x) => {
-                    let port = &serialport::available_ports().unwrap()[x].port_name;
-                    match serialport::new(port, SERIAL_BAUD_RATE).open() {
-                        Ok(mut sp) => {
-                            sp.set_data_bits(serialport::DataBits::Eight).unwrap();
-                            sp.set_flow_control(serialport::FlowControl::None).unwrap();
-                            sp.set_parity(serialport::Parity::None).unwrap();
-                            sp.set_stop_bits(serialport::StopBits::One).unwrap();
-                            sp.set_timeout(Duration::from_millis(1000)).unwrap();
-                            *serial_port.lock().unwrap() = Some(sp);
-                        }
-                        Err(e) => {
-                            winconsole::window::show(true);
-                            let mut stdoutl = io::stdout().lock();
-                            clr_write!(stdout, (Red, true), stdoutl, "Couldn't open serial port \"{port}\": ");
-                            clr_write!(stdout, Red, stdoutl, "{}\n", e.to_string());
-                            stdoutl.flush().unwrap();
-                        }
-                    }
+// Calls on_change whenever the config file's mtime changes, until it returns false.
+fn watch_config(path: &Path, mut on_change: impl FnMut() -> bool) {
+    let modified = || fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last = modified();
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let m = modified();
+        if m != last {
+            last = m;
+            if !on_change() {break}
+        }
+    }
+}
 
-                    let mut i = 0;
-                    let mut tray_lock = tray_icon.lock().unwrap();
-                    while tray_lock.get_menu_item_checkable(Events::SerialPortChanged(i)).is_some() {
-                        _=tray_lock.set_menu_item_checkable(Events::SerialPortChanged(i), i==x);
-                        i+=1;
-                    }
-                }
+fn current_lock() -> Lock {
+    LOCK.lock().unwrap().at(Local::now())
+}
 
-                Events::Factorial => {
-                    let hidden = console_to_fg(&mut tray_icon.lock().unwrap());
-                    let n = {
-                        let mut stdoutl = io::stdout().lock();
-    
-                        clr_write!(stdout, (Cyan, true), stdoutl, "Input number to calculate the factorial of: ");
-                        stdoutl.flush().unwrap();
-                        let n = input(|buffer| {
-                            if let Ok(n) = buffer.trim().parse::<u64>() {Some(n)} else {None}
-                        });
-                        clr_write!(stdout, (Cyan, true), stdoutl, "Calculating...\n");
-                        stdoutl.flush().unwrap();
-                        n
-                    };
-                    let mut sw = Stopwatch::start_new();
-
-                    //let thread_count = n/100;
-                    let (tx, rx) = mpsc::sync_channel::<BigUint>(FACTORIAL_THREAD_COUNT as usize);
-                    let result = Arc::new(Mutex::new(1u8.to_biguint().unwrap()));
-                    let resultc = Arc::clone(&result);
-
-                    thread::spawn(move || {
-                        let mut resl = resultc.lock().unwrap();
-                        let mut count = 0;
-                        let mut _stdout = StandardStream::stdout(ColorChoice::Always);
-                        while count <= FACTORIAL_THREAD_COUNT {
-                            {
-                                let mut stdoutl = io::stdout().lock();
-                                execute!(stdoutl, cursor::MoveToColumn(0)).unwrap();
-                                clr_write!(_stdout, (Cyan, true), stdoutl, "Threads finished: ");
-                                clr_write!(_stdout, (Magenta, true), stdoutl, "{}", count);
-                                clr_write!(_stdout, (Cyan, true), stdoutl, "/");
-                                clr_write!(_stdout, (Magenta, true), stdoutl, "{}", FACTORIAL_THREAD_COUNT);
-                                stdoutl.flush().unwrap();
-                            }
-                            if let Ok(rc) = rx.recv() {*resl *= rc;}
-                            count+=1;
-                        }
-                    });
-
-                    let ops = n/FACTORIAL_THREAD_COUNT;
-                    for i in 0..FACTORIAL_THREAD_COUNT {
-                        let tx_c = tx.clone();
-                        thread::spawn(move || {
-                            let _i = ops*i+1;
-                            let mut local_result = 1u8.to_biguint().unwrap();
-                            for j in _i.._i+ops {
-                                local_result *= j;
-                            }
-                            tx_c.send(local_result).unwrap();
-                        });
-                    }
+fn is_locked() -> bool {
+    current_lock() != Lock::Off
+}
 
-                    /*(0..thread_count).into_par_iter().for_each(move |i| {
-                        let _i = i*100+1;
-                        let mut local_result = 1u8.to_biguint().unwrap();
-                        for j in _i.._i+100 {
-                            local_result *= j;
-                        }
-                        tx.send(local_result).unwrap();
-                    });*/
-                    {_=result.lock().unwrap();}
-                    let mut resl = Arc::try_unwrap(result).unwrap().into_inner().unwrap();
-                    for i in n-n%FACTORIAL_THREAD_COUNT..n {
-                        resl *= i+1;
-                    }
+// Locks or unlocks every device, and writes it down so the other instances (and `everythingdoer lock`) agree.
+fn set_lock(path: &Path, lock: Lock) -> bool {
+    let mut current = LOCK.lock().unwrap(); // so watch_lock() doesn't read the old file meanwhile
+    if let Err(e) = lock.save(path) {
+        error!(Display, "Couldn't write `{}`: {e}", path.display());
+        return false;
+    }
+    *current = lock;
+    info!(Display, "Orientation `{lock}`.");
+    true
+}
 
-                    let calc_time = sw.elapsed_ms();
-                    {
-                        let mut stdoutl = io::stdout().lock();
-                        clr_write!(stdout, (Cyan, true), stdoutl, "\nCalculated, converting to decimal...");
-                        stdoutl.flush().unwrap();
-                    }
-                    sw.restart();
-                    
-                    let reslv = resl.to_radix_be(10).into_iter().map(|c| {c+0x30}).collect::<Vec<u8>>();
-                    let resl_sn1 = format!("{}.{}{}{}{}", reslv[0] as char, reslv[1] as char, reslv[2] as char, reslv[3] as char, reslv[4] as char);
-                    let resl_sn2 = format!("{}", reslv.len()-1);
-
-                    let mut buffer = [0u8];
-                    {
-                        let mut stdoutl = io::stdout().lock();
-                        clr_write!(stdout, (Cyan, true),    stdoutl, "\nFactorial of ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{n}");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, " = ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{resl_sn1}");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, "e");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{resl_sn2}");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, ". Calculated in ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{calc_time}ms");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, ", converted to decimal in ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{}ms", sw.elapsed_ms());
-                        clr_write!(stdout, (Cyan, true),    stdoutl, ". Save to file (space) or display (any)? ");
-                        stdoutl.flush().unwrap();
-
-    
-                        enable_raw_mode().unwrap();
-                        io::stdin().read(&mut buffer).unwrap();
-                    }
+// For the tray and the hotkey, locks until it's unlocked again.
+#[cfg(windows)]
+fn toggle_lock(path: &Path) -> bool {
+    set_lock(path, if is_locked() {Lock::Off} else {Lock::On})
+}
 
-                    if buffer[0] == b' ' {
-                        {
-                            let mut stdoutl = io::stdout().lock();
-                            clr_write!(stdout, (Cyan, true), stdoutl, "\nWriting to file..."); stdoutl.flush().unwrap();
-                            stdoutl.flush().unwrap();
-                        }
-                        let path = format!(r"C:\Users\Roman\Desktop\everythingdoer\src\factorial\{}.txt", n);
-                        let mut f = File::create(&path).unwrap();
-
-                        _=f.write_all(&reslv);
-                        write!(f, "\nScientific notation: {resl_sn1}e{resl_sn2}\nCalculation time = {calc_time}ms");
-
-                        _=open::that(path);
-                    } else {
-                        let mut stdoutl = io::stdout().lock();
-                        clr_write!(stdout, (Cyan, true), stdoutl, "\nWriting to console...\n");
-                        stdoutl.flush().unwrap();
-                        _=io::stdout().write_all(&reslv);
-                        stdoutl.flush().unwrap();
-                        clr_write!(stdout, (Cyan, true), stdoutl, "\nScientific notation: ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{resl_sn1}");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, "e");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{resl_sn2}");
-                        clr_write!(stdout, (Cyan, true),    stdoutl, "\nCalculation time = ");
-                        clr_write!(stdout, (Magenta, true), stdoutl, "{calc_time}ms");
-                        stdoutl.flush().unwrap();
-                    }
-                    disable_raw_mode().unwrap();
-                    println!("\nDone.");
+// Picks up locks made elsewhere and unlocks once the lock expires, calling on_change whenever it changes, until it
+// returns false. Checks right away, so a lock from before starting up applies.
+fn watch_lock(path: &Path, mut on_change: impl FnMut() -> bool) {
+    loop {
+        let mut current = LOCK.lock().unwrap();
+        let file = Lock::load(path).unwrap_or_else(|e| {
+            debug!(Display, "Couldn't read `{}`: {e}", path.display());
+            *current
+        });
+        let lock = file.at(Local::now());
+        if lock != *current {
+            if lock == Lock::Off && file != Lock::Off {
+                info!(Display, "Orientation lock expired, autorotating again.");
+                _=Lock::Off.save(path);
+            } else {
+                info!(Display, "Orientation `{lock}`.");
+            }
+            *current = lock;
+            drop(current);
+            if !on_change() {break}
+        } else {
+            drop(current);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
 
-                    if hidden {winconsole::window::hide();}
-                }
-                /*Events::CudaFactorial => {
-                    let path = format!(r"{}\src\external\CudaFactorial\x64\Release\CudaFactorial.exe", env!("CARGO_MANIFEST_DIR")); //don't ever do this. ever.
-                    _=std::process::Command::new(path).spawn().unwrap();
-                }*/
+// Registers the hotkey on a thread of its own (WM_HOTKEY goes to the registering thread's queue), and calls on_press
+// every time it's pressed, until it returns false.
+#[cfg(windows)]
+fn spawn_hotkey(hotkey: everythingdoer::lock::Hotkey, on_press: impl Fn() -> bool + Send + 'static) {
+    use windows::Win32::{Foundation::HWND, UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY}}};
+    use everythingdoer::lock::Key;
+
+    thread::spawn(move || unsafe {
+        let mut modifiers = MOD_NOREPEAT.0;
+        for (on, m) in [(hotkey.ctrl, MOD_CONTROL), (hotkey.alt, MOD_ALT), (hotkey.shift, MOD_SHIFT), (hotkey.win, MOD_WIN)] {
+            if on {modifiers |= m.0}
+        }
+        let vk = match hotkey.key {
+            Key::Char(c) => c as u32, // the same as their virtual-key codes
+            Key::F(n) => VK_F1.0 as u32 + n as u32 - 1
+        };
+        if !RegisterHotKey(HWND(0), 1, HOT_KEY_MODIFIERS(modifiers), vk).as_bool() {
+            error!(Tray, "Couldn't register the hotkey `{hotkey}`, something else probably has it.");
+            return;
+        }
+        info!(Tray, "`{hotkey}` toggles the orientation lock.");
 
-                //_ => ()
-            }
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+            if msg.message == WM_HOTKEY && !on_press() {break}
         }
     });
 }
 
+// None if the config didn't change or is invalid, in which case the current one is kept.
+fn reload_config(path: &Path, current: &Config) -> Option<Config> {
+    let new = match Config::load(Some(path)) {
+        Ok((new, _)) => new,
+        Err(e) => {
+            error!(Config, "{e}");
+            warn!(Config, "Keeping the previous config.");
+            return None;
+        }
+    };
+    if new == *current {return None}
 
-fn rotate_monitor(monitor_id: u32, ori: Orientation, tray_icon: &mut TrayIcon<Events>, current_ori: &mut u32, manual: bool) {
-    unsafe {
-        let mut d = DISPLAY_DEVICEA::default();
-        d.cb = mem::size_of::<DISPLAY_DEVICEA>() as u32;
-        let mut dm = DEVMODEA::default();
-        
-        if EnumDisplayDevicesA(
-            PCSTR::null(), monitor_id, &mut d, 0
-        ) != BOOL::from(false) {
-            if EnumDisplaySettingsA(
-                PCSTR::from_raw(mem::transmute(&d.DeviceName)),
-                ENUM_CURRENT_SETTINGS, &mut dm
-            ) != BOOL::from(false) {
-                if (dm.Anonymous1.Anonymous2.dmDisplayOrientation + ori as u32)%2==1 {
-                    let temp = dm.dmPelsHeight;
-                    dm.dmPelsHeight = dm.dmPelsWidth;
-                    dm.dmPelsWidth = temp;
-                }
+    info!(Config, "Config changed, applying...");
+    Some(new)
+}
 
-                dm.Anonymous1.Anonymous2.dmDisplayOrientation = ori as u32;
-
-                let ret = ChangeDisplaySettingsExA(
-                    PCSTR::from_raw(mem::transmute(&d.DeviceName)),
-                    &dm, HWND::default(), CDS_UPDATEREGISTRY, mem::zeroed()
-                );
-                let mut stdout = StandardStream::stdout(ColorChoice::Always);
-                if ret == DISP_CHANGE_SUCCESSFUL {
-                    if manual {
-                        for &o in &[Orientation::Landscape, Orientation::Portrait, Orientation::LandscapeFlipped, Orientation::PortraitFlipped] {
-                            _=tray_icon.set_menu_item_checkable(Events::SerialRotateMonitor(o), o==ori);
-                        }
+// Brings devices in line with the new config: reopens ports, resends the tuning, adds & removes bindings.
+fn apply_config(devices: &mut Vec<Arc<Mutex<Device>>>, display: &Arc<Mutex<Box<dyn DisplayBackend>>>, new: &Config) {
+    let mut old = std::mem::take(devices).into_iter();
+    for binding in &new.autorotate.bindings {
+        let was_on = match old.next() {
+            Some(device) => {
+                let mut dev = device.lock().unwrap();
+                if dev.serial == new.serial_for(binding) && dev.binding.port == binding.port && dev.binding.usb == binding.usb && dev.host == new.autorotate.host {
+                    if dev.binding.display != binding.display {
+                        dev.binding.display = binding.display;
+                        dev.current_ori = display.lock().unwrap().orientation(binding.display).unwrap_or(dev.current_ori);
                     }
-                    let mut stdoutl = io::stdout().lock();
-                    clr_write!(stdout, Green, stdoutl, "OK\n");
-                    *current_ori = dm.Anonymous1.Anonymous2.dmDisplayOrientation;
-                    stdoutl.flush().unwrap();
-                } else {
-                    let DISP_CHANGE(i) = ret;
-                    let mut stdoutl = io::stdout().lock();
-                    clr_write!(stdout, (Red, true), stdoutl, "Couldn't rotate screen: ");
-                    clr_write!(stdout, Red, stdoutl, "DISP_CHANGE({})\n", i);
-                    stdoutl.flush().unwrap();
+                    dev.binding.profiles = binding.profiles.clone(); // used from the next rotation on
+                    set_tuning(&mut dev, new.autorotate.tuning());
+                    dev.gate.settle = new.autorotate.settle;
+                    drop(dev);
+                    devices.push(device);
+                    continue;
                 }
+                // replaced by a new device with its own listener, the old one stops once this was the last reference
+                let was_on = dev.autorotate;
+                set_autorotate(&mut dev, false);
+                dev.client = None; //the port has to be closed before it can be reopened
+                was_on
             }
+            None => false
+        };
+
+        // opened without holding any locks, like the listener reconnects
+        let device = Arc::new(Mutex::new(open_device(binding, new, display)));
+        if was_on {set_autorotate(&mut device.lock().unwrap(), true);}
+        spawn_listener(&device, display);
+        devices.push(device);
+    }
+    for device in old {
+        let mut dev = device.lock().unwrap();
+        set_autorotate(&mut dev, false);
+        dev.client = None;
+    }
+}
+
+// USB ports get printed the way they're pinned in the config
+fn print_ports() {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            error!(Serial, "Couldn't list serial ports: {e}");
+            return;
+        }
+    };
+    for port in ports {
+        match (UsbId::of(&port), &port.port_type) {
+            (Some(usb), SerialPortType::UsbPort(info)) => {
+                let product = info.product.as_deref().unwrap_or("unknown device");
+                info!(Serial, "`{}`: {product}, `usb = {usb}`", port.port_name);
+            }
+            (_, port_type) => info!(Serial, "`{}`: {port_type:?}", port.port_name)
         }
     }
 }
 
-fn serial_send<F, S: AsRef<str> + std::fmt::Display>(port: &mut Box<dyn SerialPort>, send: &[u8], send_str: S, mut on_recv: F)
-where F: FnMut(&mut Box<dyn SerialPort>, &mut StdoutLock) {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+fn test_comms(client: &mut Client<Box<dyn SerialPort>>) -> bool {
+    let ret = serial_send(Request::Sync, || client.sync()).is_some();
+    if ret {serial_success(Response::Ack, "")}
+    ret
+}
+
+fn query_status(client: &mut Client<Box<dyn SerialPort>>) -> Option<bool> {
+    print_firmware(client);
+    let running = serial_send(Request::Query, || client.query())?;
+    if running {serial_success(Response::Ack, " - autorotation is running")}
+    else       {serial_success(Response::Nak, " - autorotation is not running")}
+    Some(running)
+}
+
+// Decodes a capture the way the client's reader thread would have. Like print_factorial(), the output goes straight to stdout.
+fn replay_capture(path: &Path) -> bool {
+    let records = match capture::read(path) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(Serial, "Couldn't read capture `{}`: {e}", path.display());
+            return false;
+        }
+    };
+
+    let mut modes = HashMap::new(); // port -> mode
+    let mut decoders: HashMap<(String, bool), (FrameDecoder, _)> = HashMap::new(); // (port, sent) -> (decoder, time of the last record)
     let mut stdoutl = io::stdout().lock();
-    clr_write!(stdout, (Cyan, true), stdoutl, "Sending ");
-    clr_write!(stdout, (Magenta, true), stdoutl, "{send_str}");
-    clr_write!(stdout, (Cyan, true), stdoutl, "...");
+    for record in &records {
+        let time = record.time.format("%H:%M:%S%.6f");
+        let (sent, bytes) = match record.event {
+            Event::Sent(ref bytes) => (true, bytes),
+            Event::Received(ref bytes) => (false, bytes),
+            Event::Mode(mode) => {
+                _=writeln!(stdoutl, "{time} {} mode {mode}", record.port);
+                modes.insert(&record.port, mode);
+                continue;
+            }
+        };
+        let mut print = |what: String| _=writeln!(stdoutl, "{time} {} {} {what}", record.port, if sent {">"} else {"<"});
 
-    stdoutl.flush().unwrap();
+        if modes.get(&record.port) != Some(&Mode::Framed) {
+            for &b in bytes {print(describe_payload(sent, &[b]))}
+            continue;
+        }
+
+        let (decoder, last) = decoders.entry((record.port.clone(), sent)).or_insert((FrameDecoder::default(), record.time));
+        // the reader gives up on a partial frame once the line's been quiet for a read timeout
+        if (record.time - *last).to_std().unwrap_or_default() >= Duration::from_millis(100) {decoder.flush()}
+        *last = record.time;
+
+        for &b in bytes {
+            let idle = decoder.is_idle();
+            decoder.push(b);
+            if idle && b != STX {print(format!("bare {}", describe_payload(sent, &[b])))}
+            while let Some(Frame {seq, payload}) = decoder.pop() {
+                print(format!("frame {seq}: {}", describe_payload(sent, &payload)));
+            }
+        }
+    }
+    drop(stdoutl);
+
+    for ((port, sent), (decoder, _)) in &decoders {
+        if decoder.discarded > 0 {
+            warn!(Serial, "`{port}`: `{}` bytes {} weren't part of a valid frame.", decoder.discarded, if *sent {"sent"} else {"received"});
+        }
+    }
+    info!(Serial, "Replayed `{}` records.", records.len());
+    true
+}
 
-    if let Err(e) = port.write(send) {
-        clr_write!(stdout, (Red, true), stdoutl, "ERR: Couldn't write - ");
-        clr_write!(stdout, Red, stdoutl, "{}", e.to_string());
+// e.g. "DC2 (Portrait)", "[Landscape, 65]", "0xff"
+fn describe_payload(sent: bool, payload: &[u8]) -> String {
+    let hex = || payload.iter().map(|b| format!("{b:#04x}")).collect::<Vec<_>>().join(" ");
+    if sent {
+        match *payload {
+            [SI | DLE | RS, ..] => Request::decode_payload(payload).map_or_else(hex, |r| r.to_string()),
+            [b] => Request::decode(b).map_or_else(hex, |r| r.to_string()),
+            [o, threshold] => match Orientation::from_u8(o) {
+                Some(orientation) => Request::Settings {orientation, threshold}.to_string(),
+                None => hex()
+            }
+            _ => hex()
+        }
     } else {
-        if let Err(e) = port.flush() {
-            clr_write!(stdout, (Red, true), stdoutl, "ERR: Couldn't flush - ");
-            clr_write!(stdout, Red, stdoutl, "{}", e.to_string());
-        } else {
-            clr_write!(stdout, Green, stdoutl, "\nSuccess");
-            clr_write!(stdout, (Cyan, true), stdoutl, ", waiting for response...");
-            stdoutl.flush().unwrap();
-            
-            let sw = Stopwatch::start_new();
-
-            loop {
-                match port.bytes_to_read() {
-                    Ok(n) => if n > 0 {on_recv(port, &mut stdoutl); break;} else if sw.elapsed_ms() > SERIAL_ACK_TIMEOUT {
-                        clr_write!(stdout, (Red, true), stdoutl, "ERR: Timeout.\n");
-                        break;
-                    }
-                    Err(e) => {
-                        clr_write!(stdout, (Red, true), stdoutl, "ERR: Couldn't get bytes to read - ");
-                        clr_write!(stdout, Red, stdoutl, "{}\n", e.to_string());
-                        break;
-                    }
-                }
+        match *payload {
+            [SOH, ..] => Firmware::decode(payload).map_or_else(hex, |fw| format!("HELLO reply: {fw}, commands {}", fw.command_names())),
+            [SO, ref tuning @ ..] => Tuning::decode(tuning).map_or_else(hex, |tuning| format!("SO reply: {tuning}")),
+            [DLE, ..] => Sample::decode(payload).map_or_else(hex, |sample| format!("telemetry: {sample}")),
+            [RS, ..] => Reading::decode(payload).map_or_else(hex, |reading| format!("reading: {reading}")),
+            [b] => match Response::decode(b) {
+                r @ Response::Orientation(o) => format!("{r} ({o:?})"),
+                r => r.to_string()
             }
+            _ => hex()
         }
     }
-    stdoutl.flush().unwrap();
 }
 
-fn console_to_fg(tray_icon: &mut TrayIcon<Events>) -> bool {
-    let mut hidden_before = true;
-    if let Some(oldv) = tray_icon.get_menu_item_checkable(Events::HideConsole) {
-        if !oldv {
-            hidden_before = false;
-            winconsole::window::hide();
+// Prints the firmware's tuning, and changes whatever's given.
+fn tune(client: &mut Client<Box<dyn SerialPort>>, threshold: Option<u8>, hysteresis: Option<u8>, debounce_ms: Option<u16>) -> bool {
+    let current = match serial_send(Request::GetTuning, || client.get_tuning()) {
+        Some(tuning) => tuning,
+        None => return false
+    };
+    info!(Serial, "Tuning: `{current}`.");
+
+    let tuning = Tuning {
+        threshold: threshold.unwrap_or(current.threshold),
+        hysteresis: hysteresis.unwrap_or(current.hysteresis),
+        debounce_ms: debounce_ms.unwrap_or(current.debounce_ms)
+    };
+    if tuning == current {return true}
+    if let Err(e) = tuning.validate() {
+        error!(Serial, "{e}");
+        return false;
+    }
+    match serial_send(Request::SetTuning(tuning), || client.set_tuning(tuning)) {
+        Some(true) => {
+            serial_success(Response::Ack, "");
+            true
         }
+        Some(false) => {
+            serial_error(Response::Nak);
+            false
+        }
+        None => false
     }
-    winconsole::window::show(true);
-    hidden_before
 }
 
-fn input<T, F>(mut condition: F) -> T where F: FnMut(&str) -> Option<T> {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    loop {
-        let mut buffer = String::new();
-        io::stdin().read_line(&mut buffer).unwrap();
+fn print_firmware(client: &Client<Box<dyn SerialPort>>) {
+    match client.firmware() {
+        Some(fw) => info!(Serial, "Firmware: `{fw}`, commands: `{}`.", fw.command_names()),
+        None => info!(Serial, "Firmware: unknown (`{}` protocol), assuming it supports every command.", client.mode())
+    }
+}
+
+fn recalibrate(client: &mut Client<Box<dyn SerialPort>>) -> bool {
+    let ret = serial_send(Request::Recalibrate, || client.recalibrate()).is_some();
+    if ret {serial_success(Response::Ack, "")}
+    ret
+}
+
+// Returns (digits, mantissa, exponent, calculation time in ms)
+fn calc_factorial(n: u64, thread_count: u64) -> (Vec<u8>, String, String, i64) {
+    let mut sw = Stopwatch::start_new();
+    info!(Factorial, "Calculating the factorial of `{n}` on `{thread_count}` threads...");
+
+    let resl = factorial::factorial(n, thread_count, |count| {
+        debug!(Factorial, "Threads finished: `{count}`/`{thread_count}`");
+    });
+
+    let calc_time = sw.elapsed_ms();
+    info!(Factorial, "Calculated, converting to decimal...");
+    sw.restart();
+
+    let reslv = factorial::to_decimal(&resl);
+    let (resl_sn1, resl_sn2) = factorial::scientific(&reslv);
+
+    info!(Factorial, "Factorial of `{n}` = `{resl_sn1}e{resl_sn2}`. Calculated in `{calc_time}ms`, converted to decimal in `{}ms`.", sw.elapsed_ms());
+    (reslv, resl_sn1, resl_sn2, calc_time)
+}
+
+fn write_factorial(path: &Path, reslv: &[u8], resl_sn1: &str, resl_sn2: &str, calc_time: i64) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    f.write_all(reslv)?;
+    write!(f, "\nScientific notation: {resl_sn1}e{resl_sn2}\nCalculation time = {calc_time}ms")
+}
+
+// The digits are the output, not a log message, so they go straight to stdout.
+fn print_factorial(reslv: &[u8], resl_sn1: &str, resl_sn2: &str, calc_time: i64) {
+    let mut stdoutl = io::stdout().lock();
+    _=stdoutl.write_all(reslv);
+    _=writeln!(stdoutl, "\nScientific notation: {resl_sn1}e{resl_sn2}\nCalculation time = {calc_time}ms");
+    _=stdoutl.flush();
+}
+
+// Applies the orientation's profile too, if the binding has one.
+fn rotate_monitor(display: &mut dyn DisplayBackend, monitor_id: u32, ori: Orientation, profile: Option<&Profile>, current_ori: &mut Orientation) -> bool {
+    let profile = profile.copied().unwrap_or_default();
+    match display.set_mode(monitor_id, ori, &profile) {
+        Ok(()) => {
+            if profile == Profile::default() {info!(Display, "Rotated monitor `{monitor_id}` to `{ori:?}`.")}
+            else {info!(Display, "Rotated monitor `{monitor_id}` to `{ori:?}` ({profile}).")}
+            *current_ori = ori;
+            true
+        }
+        Err(e) => {
+            error!(Display, "Couldn't rotate monitor `{monitor_id}` to `{ori:?}`: {e}");
+            false
+        }
+    }
+}
 
-        if let Some(v) = condition(&buffer) {break v}
+fn serial_send<T, F, S: std::fmt::Display>(send_str: S, op: F) -> Option<T>
+where F: FnOnce() -> Result<T, protocol::Error> {
+    info!(Serial, "Sending `{send_str}`...");
 
-        let mut stdoutl = io::stdout().lock();
-        clr_write!(stdout, (Magenta, true), stdoutl, "Invalid value, try again: ");
-        _=stdout.reset();
-        stdoutl.flush().unwrap();
+    let ret = op();
+    if let Err(ref e) = ret {
+        error!(Serial, "Couldn't send `{send_str}`: {e}");
     }
-}
\ No newline at end of file
+    ret.ok()
+}
+
+fn serial_success(received: Response, note: &str) {
+    info!(Serial, "Success: `{received}` received{note}.");
+}
+
+fn serial_error(received: Response) {
+    error!(Serial, "`{received}` received.");
+}
+
+fn show_console() {
+    #[cfg(windows)]
+    if !HEADLESS.load(Ordering::Relaxed) {winconsole::window::show(true);}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use everythingdoer::display::{MockBackend, Profiles};
+    #[cfg(unix)]
+    use {std::sync::mpsc, serialport::TTYPort, everythingdoer::{simulator, config::{SettleConfig, HostConfig, Filter}}};
+
+    #[test]
+    fn backs_off() {
+        let waits: Vec<u64> = std::iter::successors(Some(RECONNECT_BACKOFF_MIN), |&b| Some(next_backoff(b))).take(8).map(|b| b.as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 30, 30, 30]);
+    }
+
+    #[test]
+    fn rotates_monitors() {
+        let mut display = MockBackend::new().with_output("DP-1", 1920, 1080).with_output("HDMI-1", 2560, 1440);
+        let mut current_ori = Orientation::Landscape;
+
+        assert!(rotate_monitor(&mut display, 1, Orientation::Portrait, None, &mut current_ori));
+        assert_eq!(current_ori, Orientation::Portrait);
+        assert_eq!(display.resolution(1).unwrap(), (1440, 2560));
+        assert!(rotate_monitor(&mut display, 1, Orientation::PortraitFlipped, None, &mut current_ori));
+        assert_eq!(display.resolution(1).unwrap(), (1440, 2560)); // still portrait
+        assert!(rotate_monitor(&mut display, 1, Orientation::LandscapeFlipped, None, &mut current_ori));
+        assert_eq!(display.resolution(1).unwrap(), (2560, 1440));
+        assert_eq!(display.resolution(0).unwrap(), (1920, 1080));
+        assert_eq!(display.changes, [(1, Orientation::Portrait), (1, Orientation::PortraitFlipped), (1, Orientation::LandscapeFlipped)]);
+    }
+
+    #[test]
+    fn applies_profiles() {
+        let mut display = MockBackend::new().with_output("DP-1", 1920, 1080).with_output("HDMI-1", 2560, 1440);
+        let profiles = Profiles {
+            portrait: Some(Profile {resolution: Some((1080, 1920)), refresh_hz: Some(30), scale_percent: Some(125), primary: Some(true)}),
+            landscape: Some(Profile {refresh_hz: Some(144), ..Profile::default()}),
+            ..Profiles::default()
+        };
+        let mut current_ori = Orientation::Landscape;
+
+        assert!(rotate_monitor(&mut display, 1, Orientation::Portrait, profiles.get(Orientation::Portrait), &mut current_ori));
+        let o = &display.outputs[1];
+        assert_eq!((o.width, o.height, o.refresh_hz, o.scale_percent, o.primary), (1080, 1920, 30, 125, true));
+        assert!(!display.outputs[0].primary);
+
+        // what the profile leaves out stays as it is
+        assert!(rotate_monitor(&mut display, 1, Orientation::Landscape, profiles.get(Orientation::Landscape), &mut current_ori));
+        let o = &display.outputs[1];
+        assert_eq!((o.width, o.height, o.refresh_hz, o.scale_percent, o.primary), (1920, 1080, 144, 125, true));
+        assert!(profiles.get(Orientation::LandscapeFlipped).is_none());
+    }
+
+    #[test]
+    fn keeps_current_ori_on_failure() {
+        let mut display = MockBackend::new().with_output("DP-1", 1920, 1080);
+        let mut current_ori = Orientation::Landscape;
+
+        display.failing = true;
+        assert!(!rotate_monitor(&mut display, 0, Orientation::Portrait, None, &mut current_ori));
+        assert_eq!(current_ori, Orientation::Landscape);
+        assert!(!rotate_monitor(&mut display, 1, Orientation::Portrait, None, &mut current_ori)); // no such output
+        assert_eq!(current_ori, Orientation::Landscape);
+        assert_eq!((display.orientation(0).unwrap(), display.resolution(0).unwrap()), (Orientation::Landscape, (1920, 1080)));
+        assert!(display.changes.is_empty());
+
+        display.failing = false;
+        assert!(rotate_monitor(&mut display, 0, Orientation::Portrait, None, &mut current_ori));
+        assert_eq!(current_ori, Orientation::Portrait);
+    }
+
+    // The simulator on the other end of a pty, stepped until `tilt` is dropped. Hands the simulator back once it stopped.
+    #[cfg(unix)]
+    fn simulate(set_up: impl FnOnce(&mut simulator::Device<TTYPort>)) -> (Client<Port>, String, mpsc::Sender<f32>, thread::JoinHandle<simulator::Device<TTYPort>>) {
+        let (mut master, mut slave) = TTYPort::pair().expect("couldn't create pty");
+        slave.set_exclusive(false).unwrap();
+        master.set_timeout(Duration::from_millis(10)).unwrap();
+        slave.set_timeout(Duration::from_millis(100)).unwrap();
+        let (tilt, tilts) = mpsc::channel();
+        let mut sim = simulator::Device::new(master);
+        set_up(&mut sim);
+        let sim = thread::spawn(move || loop {
+            match tilts.try_recv() {
+                Ok(deg) => sim.tilt = deg,
+                Err(mpsc::TryRecvError::Empty) => (),
+                Err(mpsc::TryRecvError::Disconnected) => break sim
+            }
+            sim.step().unwrap();
+        });
+
+        let name = slave.name().unwrap();
+        let input = slave.try_clone().unwrap();
+        let mut client = Client::new(Box::new(slave) as Port, input, Duration::from_secs(1));
+        assert_eq!(client.negotiate().unwrap(), Mode::Framed);
+        (client, name, tilt, sim)
+    }
+
+    // A device on the simulator, the way tray::run() and headless::run() set them up, tilted until the display has to follow
+    #[cfg(unix)]
+    fn autorotate_simulated(host: Option<HostConfig>) {
+        let (client, port, tilt, sim) = simulate(|_| ());
+        let display: Arc<Mutex<Box<dyn DisplayBackend>>> = Arc::new(Mutex::new(Box::new(MockBackend::new().with_output("DP-1", 1920, 1080))));
+        let binding = Binding {port, display: 0, usb: None, serial: Default::default(), profiles: Profiles::default()};
+        let tuning = Tuning {threshold: 45, hysteresis: 5, debounce_ms: 0};
+        let mut dev = Device::new(binding, SerialConfig::default(), tuning, SettleConfig::default(), host, Orientation::Landscape);
+        dev.client = Some(client);
+        assert!(set_autorotate(&mut dev, true));
+        let device = Arc::new(Mutex::new(dev));
+        spawn_listener(&device, &display);
+
+        tilt.send(10.0).unwrap(); // upright, and what the host calibrates on
+        thread::sleep(Duration::from_millis(300));
+        tilt.send(80.0).unwrap();
+        let rotated = Instant::now() + Duration::from_secs(2);
+        while device.lock().unwrap().current_ori == Orientation::Landscape && Instant::now() < rotated {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(device.lock().unwrap().current_ori, Orientation::PortraitFlipped);
+        assert_eq!(display.lock().unwrap().orientation(0).unwrap(), Orientation::PortraitFlipped);
+
+        drop(tilt); // before the pty closes
+        sim.join().unwrap();
+        drop(device);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn autorotates_on_pushes() {
+        autorotate_simulated(None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn autorotates_on_readings() {
+        autorotate_simulated(Some(HostConfig {interval_ms: 20, filter: Filter::None}));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tunes_only_what_hello_listed() {
+        let tuning = Tuning {threshold: 65, hysteresis: 5, debounce_ms: 0};
+        let (mut client, _, tilt, sim) = simulate(|_| ());
+        client.hello().unwrap();
+        assert!(tune(&mut client, Some(50), None, Some(100)));
+        assert!(!tune(&mut client, Some(90), None, None)); // out of range, not sent
+        drop(tilt);
+        assert_eq!(sim.join().unwrap().tracker.tuning, Tuning {threshold: 50, hysteresis: 5, debounce_ms: 100});
+
+        // firmware that can be asked but not told
+        let (mut client, _, tilt, sim) = simulate(|sim| sim.firmware.commands.retain(|&b| b != SI));
+        client.hello().unwrap();
+        assert!(!tune(&mut client, Some(50), None, None));
+        drop(tilt);
+        assert_eq!(sim.join().unwrap().tracker.tuning, tuning);
+    }
+}
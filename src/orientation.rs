@@ -0,0 +1,45 @@
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Orientation { // same values as DMDO_DEFAULT/90/180/270, and as the firmware's current_ori
+    Landscape = 0,
+    Portrait  = 1,
+    LandscapeFlipped = 2,
+    PortraitFlipped = 3
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [Orientation::Landscape, Orientation::Portrait, Orientation::LandscapeFlipped, Orientation::PortraitFlipped];
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        Self::ALL.get(v as usize).copied()
+    }
+
+    // `quarters` steps through ALL, either way
+    pub fn turned(self, quarters: i8) -> Self {
+        Self::ALL[(self as i32 + quarters as i32).rem_euclid(4) as usize]
+    }
+
+    // what FromStr takes, and what the config's profiles are keyed by
+    pub fn name(self) -> &'static str {
+        match self {
+            Orientation::Landscape        => "landscape",
+            Orientation::Portrait         => "portrait",
+            Orientation::LandscapeFlipped => "landscape-flipped",
+            Orientation::PortraitFlipped  => "portrait-flipped"
+        }
+    }
+}
+
+impl std::str::FromStr for Orientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "landscape" => Ok(Orientation::Landscape),
+            "portrait"  => Ok(Orientation::Portrait),
+            "landscape-flipped" => Ok(Orientation::LandscapeFlipped),
+            "portrait-flipped"  => Ok(Orientation::PortraitFlipped),
+            _ => Err(format!("Unknown orientation \"{s}\", expected landscape, portrait, landscape-flipped or portrait-flipped."))
+        }
+    }
+}
@@ -0,0 +1,1192 @@
+use std::{io::{self, Read, Write}, collections::VecDeque, time::{Duration, Instant}, fmt, thread::{self, JoinHandle}};
+use std::sync::{Arc, Mutex, PoisonError, mpsc::{self, Receiver, Sender, RecvTimeoutError}, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use crate::{orientation::Orientation, tracker, capture::{Capture, Event}};
+
+/* #region CONSTANTS */
+
+pub const SOH: u8 = 0x01;
+pub const SYN: u8 = 0x16;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const ENQ: u8 = 0x05;
+pub const DC1: u8 = 0x11;
+pub const DC2: u8 = 0x12;
+pub const DC3: u8 = 0x13;
+pub const DC4: u8 = 0x14;
+pub const SO: u8 = 0x0e;
+pub const SI: u8 = 0x0f;
+pub const DLE: u8 = 0x10;
+pub const RS: u8 = 0x1e;
+
+// Framed (v2) protocol: STX, VERSION, seq, payload length, payload, CRC-16 (big endian) over everything after STX.
+// The payload is what would've been sent bare in the legacy protocol.
+pub const STX: u8 = 0x02;
+pub const VERSION: u8 = 2;
+pub const MAX_PAYLOAD: usize = 64;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Firmware with another major version is refused, see Client::hello().
+pub const FIRMWARE_MAJOR: u8 = 1;
+
+/* #endregion */
+
+/* #region ENUMS */
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Request {
+    Sync,        // SYN, always ACKed
+    Query,       // ENQ, ACK if autorotation is running, NAK otherwise
+    Start,       // DC1, answered with ENQ (then expects Settings) or NAK if already running
+    Stop,        // DC2, ACK if autorotation was running, NAK otherwise
+    Recalibrate, // DC3, always ACKed
+    Hello,       // SOH, framed only, answered with the firmware's name, version and commands
+    GetTuning,   // SO, framed only, answered with SO and the tuning
+    SetTuning(Tuning), // SI and the tuning, framed only, ACK or NAK if it's out of range. Applies right away, even while running
+    Telemetry(u16), // DLE and the interval in ms (big endian, 0 stops it), framed only, ACKed. Samples get streamed until it's stopped
+    Raw(u16),       // RS, like Telemetry but streams the accelerometer's readings as they are
+    Settings {orientation: Orientation, threshold: u8}, // reply to the ENQ sent after Start
+    Ack          // reply to an orientation push
+}
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Response {
+    Ack, Nak, Enq,
+    Orientation(Orientation), // DC1..DC4, pushed by the device whenever it rotates
+    Unknown(u8)
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Mode {Legacy, Framed}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Frame {
+    pub seq: u8, // replies have the seq of the request, ACKs to orientation pushes the seq of the push
+    pub payload: Vec<u8>
+}
+
+// What the device said about itself in its HELLO reply:
+// SOH, major, minor, patch, command count, the commands' bytes, name (UTF-8, the rest of the payload)
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Firmware {
+    pub name: String,
+    pub version: (u8, u8, u8),
+    pub commands: Vec<u8>
+}
+
+// How the firmware decides to rotate: the angle has to stay past `threshold` for `debounce_ms`, and after rotating
+// it has to come back within threshold - hysteresis before it can rotate again.
+// On the wire: threshold, hysteresis, debounce_ms (big endian)
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Tuning {
+    pub threshold: u8, // degrees
+    pub hysteresis: u8, // degrees
+    pub debounce_ms: u16
+}
+
+// Streamed by the device while telemetry is on, in frames with seq 0: DLE, millis (u32), deviation, offset (f32s), all big endian.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sample {
+    pub millis: u32,    // the device's clock
+    pub deviation: f32, // straight from the accelerometer, degrees
+    pub offset: f32     // what it was calibrated to, plus 90° for every rotation since
+}
+
+// Streamed by the device while raw readings are on, like samples: RS, millis (u32), x, y, z (f32s, in g), all big endian.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Reading {
+    pub millis: u32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Timeout,
+    Disconnected, // the reader thread stopped after a read error
+    Unexpected(Response),
+    Unsupported(Request),  // the firmware didn't list the command in its HELLO reply
+    Incompatible(Firmware) // wrong major version
+}
+
+/* #endregion */
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Request::Sync        => vec![SYN],
+            Request::Query       => vec![ENQ],
+            Request::Start       => vec![DC1],
+            Request::Stop        => vec![DC2],
+            Request::Recalibrate => vec![DC3],
+            Request::Hello       => vec![SOH],
+            Request::GetTuning   => vec![SO],
+            Request::SetTuning(tuning) => [&[SI][..], &tuning.encode()].concat(),
+            Request::Telemetry(interval_ms) => [&[DLE][..], &interval_ms.to_be_bytes()].concat(),
+            Request::Raw(interval_ms) => [&[RS][..], &interval_ms.to_be_bytes()].concat(),
+            Request::Settings {orientation, threshold} => vec![orientation as u8, threshold],
+            Request::Ack         => vec![ACK]
+        }
+    }
+
+    // Settings can't be told apart from a command by its bytes alone, the device only expects it right after answering Start.
+    // SetTuning, Telemetry and Raw need the rest of their payload, see decode_payload().
+    pub fn decode(b: u8) -> Option<Self> {
+        match b {
+            SYN => Some(Request::Sync),
+            ENQ => Some(Request::Query),
+            DC1 => Some(Request::Start),
+            DC2 => Some(Request::Stop),
+            DC3 => Some(Request::Recalibrate),
+            SOH => Some(Request::Hello),
+            SO  => Some(Request::GetTuning),
+            ACK => Some(Request::Ack),
+            _ => None
+        }
+    }
+
+    // A whole frame's payload
+    pub fn decode_payload(payload: &[u8]) -> Option<Self> {
+        match *payload {
+            [SI, ref tuning @ ..] => Tuning::decode(tuning).map(Request::SetTuning),
+            [DLE, hi, lo] => Some(Request::Telemetry(u16::from_be_bytes([hi, lo]))),
+            [RS, hi, lo] => Some(Request::Raw(u16::from_be_bytes([hi, lo]))),
+            [b] => Request::decode(b),
+            _ => None
+        }
+    }
+
+    // Legacy firmware only understands single bytes.
+    pub fn is_framed_only(&self) -> bool {
+        matches!(self, Request::Hello | Request::GetTuning | Request::SetTuning(_) | Request::Telemetry(_) | Request::Raw(_))
+    }
+}
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Request::Sync        => write!(f, "SYN"),
+            Request::Query       => write!(f, "ENQ"),
+            Request::Start       => write!(f, "DC1"),
+            Request::Stop        => write!(f, "DC2"),
+            Request::Recalibrate => write!(f, "DC3"),
+            Request::Hello       => write!(f, "SOH"),
+            Request::GetTuning   => write!(f, "SO"),
+            Request::SetTuning(tuning) => write!(f, "SI [{tuning}]"),
+            Request::Telemetry(0) => write!(f, "DLE [off]"),
+            Request::Telemetry(interval_ms) => write!(f, "DLE [every {interval_ms}ms]"),
+            Request::Raw(0) => write!(f, "RS [off]"),
+            Request::Raw(interval_ms) => write!(f, "RS [every {interval_ms}ms]"),
+            Request::Settings {orientation, threshold} => write!(f, "[{orientation:?}, {threshold}]"),
+            Request::Ack         => write!(f, "ACK")
+        }
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> u8 {
+        match *self {
+            Response::Ack => ACK,
+            Response::Nak => NAK,
+            Response::Enq => ENQ,
+            Response::Orientation(o) => DC1 + o as u8,
+            Response::Unknown(b) => b
+        }
+    }
+
+    pub fn decode(b: u8) -> Self {
+        match b {
+            ACK => Response::Ack,
+            NAK => Response::Nak,
+            ENQ => Response::Enq,
+            DC1..=DC4 => Response::Orientation(Orientation::from_u8(b - DC1).unwrap()),
+            _ => Response::Unknown(b)
+        }
+    }
+}
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::Ack => write!(f, "ACK"),
+            Response::Nak => write!(f, "NAK"),
+            Response::Enq => write!(f, "ENQ"),
+            Response::Orientation(o) => write!(f, "DC{}", *o as u8 + 1),
+            Response::Unknown(b) => write!(f, "{b:#04x}")
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Legacy => write!(f, "legacy"),
+            Mode::Framed => write!(f, "framed")
+        }
+    }
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = vec![STX, VERSION, self.seq, self.payload.len() as u8];
+        ret.extend_from_slice(&self.payload);
+        let crc = crc16(&ret[1..]);
+        ret.extend_from_slice(&crc.to_be_bytes());
+        ret
+    }
+}
+
+impl Firmware {
+    pub fn encode(&self) -> Vec<u8> {
+        let (major, minor, patch) = self.version;
+        let mut ret = vec![SOH, major, minor, patch, self.commands.len() as u8];
+        ret.extend_from_slice(&self.commands);
+        ret.extend_from_slice(self.name.as_bytes());
+        ret
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let &[SOH, major, minor, patch, count, ref rest @ ..] = payload else {return None};
+        let (commands, name) = rest.split_at_checked(count as usize)?;
+        Some(Firmware {
+            name: String::from_utf8_lossy(name).into_owned(),
+            version: (major, minor, patch),
+            commands: commands.to_vec()
+        })
+    }
+
+    // Settings and ACKs are part of other commands' exchanges, not commands of their own.
+    pub fn supports(&self, req: Request) -> bool {
+        match req {
+            Request::Settings {..} | Request::Ack => true,
+            _ => self.commands.contains(&req.encode()[0])
+        }
+    }
+
+    // e.g. "SYN ENQ DC1", bytes that aren't known commands as hex
+    pub fn command_names(&self) -> String {
+        self.commands.iter().map(|&b| match Request::decode(b) {
+            Some(r) => r.to_string(),
+            None if b == SI => "SI".to_string(),
+            None if b == DLE => "DLE".to_string(),
+            None if b == RS => "RS".to_string(),
+            None => format!("{b:#04x}")
+        }).collect::<Vec<_>>().join(" ")
+    }
+}
+impl fmt::Display for Firmware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (major, minor, patch) = self.version;
+        write!(f, "{} v{major}.{minor}.{patch}", self.name)
+    }
+}
+
+impl Tuning {
+    pub fn encode(&self) -> [u8; 4] {
+        let [hi, lo] = self.debounce_ms.to_be_bytes();
+        [self.threshold, self.hysteresis, hi, lo]
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let &[threshold, hysteresis, hi, lo] = bytes else {return None};
+        Some(Tuning {threshold, hysteresis, debounce_ms: u16::from_be_bytes([hi, lo])})
+    }
+
+    // What the firmware NAKs
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..90).contains(&self.threshold) { // the firmware flips 180° past threshold+90
+            return Err(format!("The threshold must be between 1 and 89°, got {}°.", self.threshold));
+        }
+        if self.hysteresis >= self.threshold {
+            return Err(format!("The hysteresis ({}°) must be less than the threshold ({}°).", self.hysteresis, self.threshold));
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "threshold {}°, hysteresis {}°, debounce {}ms", self.threshold, self.hysteresis, self.debounce_ms)
+    }
+}
+
+impl Sample {
+    // what gets compared against the threshold
+    pub fn angle(&self) -> f32 {tracker::wrap(self.deviation - self.offset)}
+
+    pub fn encode(&self) -> Vec<u8> {
+        [&[DLE][..], &self.millis.to_be_bytes(), &self.deviation.to_be_bytes(), &self.offset.to_be_bytes()].concat()
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let &[DLE, ref rest @ ..] = payload else {return None};
+        let (millis, rest) = rest.split_first_chunk::<4>()?;
+        let (deviation, rest) = rest.split_first_chunk::<4>()?;
+        let offset: &[u8; 4] = rest.try_into().ok()?;
+        Some(Sample {millis: u32::from_be_bytes(*millis), deviation: f32::from_be_bytes(*deviation), offset: f32::from_be_bytes(*offset)})
+    }
+}
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "angle {:.1}° (deviation {:.1}°, offset {:.1}°) at {}ms", self.angle(), self.deviation, self.offset, self.millis)
+    }
+}
+
+impl Reading {
+    // what the firmware makes of it, see tracker::deviation()
+    pub fn deviation(&self) -> f32 {tracker::deviation(self.x, self.y)}
+
+    pub fn encode(&self) -> Vec<u8> {
+        [&[RS][..], &self.millis.to_be_bytes(), &self.x.to_be_bytes(), &self.y.to_be_bytes(), &self.z.to_be_bytes()].concat()
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let &[RS, ref rest @ ..] = payload else {return None};
+        let (millis, rest) = rest.split_first_chunk::<4>()?;
+        let (x, rest) = rest.split_first_chunk::<4>()?;
+        let (y, rest) = rest.split_first_chunk::<4>()?;
+        let z: &[u8; 4] = rest.try_into().ok()?;
+        Some(Reading {millis: u32::from_be_bytes(*millis), x: f32::from_be_bytes(*x), y: f32::from_be_bytes(*y), z: f32::from_be_bytes(*z)})
+    }
+}
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x {:.3}g, y {:.3}g, z {:.3}g (deviation {:.1}°) at {}ms", self.x, self.y, self.z, self.deviation(), self.millis)
+    }
+}
+
+// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, &b| (0..8).fold(crc ^ (b as u16) << 8, |crc, _| {
+        if crc & 0x8000 != 0 {crc << 1 ^ 0x1021} else {crc << 1}
+    }))
+}
+
+// Picks frames out of a byte stream. Anything that isn't part of a frame that checks out
+// (wrong version, bad CRC, noise between frames) is counted in `discarded` and skipped.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    ready: VecDeque<Frame>,
+    pub discarded: usize
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, b: u8) {
+        if self.buf.is_empty() && b != STX {
+            self.discarded += 1;
+            return;
+        }
+        self.buf.push(b);
+
+        if self.buf.len() == 2 && b != VERSION {return self.resync()}
+        if self.buf.len() == 4 && b as usize > MAX_PAYLOAD {return self.resync()}
+        if self.buf.len() >= 4 && self.buf.len() == 4 + self.buf[3] as usize + 2 {
+            let (body, crc) = self.buf[1..].split_at(self.buf.len() - 3);
+            if crc16(body) != u16::from_be_bytes([crc[0], crc[1]]) {return self.resync()}
+
+            self.ready.push_back(Frame {seq: self.buf[2], payload: self.buf[4..4 + self.buf[3] as usize].to_vec()});
+            self.buf.clear();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Frame> {self.ready.pop_front()}
+
+    // true when not in the middle of a frame
+    pub fn is_idle(&self) -> bool {self.buf.is_empty()}
+
+    // The line went quiet, so a partial frame isn't going to be finished. Frames get sent in one go, so it was probably
+    // noise with an STX in it, and a length that swallowed the frame that came after it.
+    pub fn flush(&mut self) {
+        while !self.buf.is_empty() {self.resync()}
+    }
+
+    // The STX wasn't the start of a frame after all, look for the next one in what came after it.
+    fn resync(&mut self) {
+        let rest = self.buf.split_off(1);
+        self.buf.clear();
+        self.discarded += 1;
+        for b in rest {self.push(b)}
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Timeout => write!(f, "Timeout."),
+            Error::Disconnected => write!(f, "The port stopped responding."),
+            Error::Unexpected(r) => write!(f, "Received unexpected code \"{r}\"."),
+            Error::Unsupported(r) => write!(f, "The firmware doesn't support \"{r}\"."),
+            Error::Incompatible(fw) => write!(f, "Firmware \"{fw}\" is incompatible, major version {FIRMWARE_MAJOR} is needed.")
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {Error::Io(e)}
+}
+
+// What the reader thread hands over to the client. Orientation pushes are ACKed by the reader and go their own way, see Client::take_pushes(),
+// and so do streamed samples and readings, see Client::take_telemetry() and take_raw().
+enum Incoming {
+    Frame(Frame),
+    Byte(u8) // everything in legacy mode, bytes outside of frames in framed mode
+}
+
+// Between a client and its reader thread
+#[derive(Default)]
+struct Shared {
+    framed: AtomicBool,
+    closed: AtomicBool,  // the client was dropped
+    stopped: AtomicBool, // reading or writing failed, the reader gives up on the port
+    discarded: AtomicUsize,
+    telemetry: AtomicBool, // samples get dropped until someone takes them
+    raw: AtomicBool,       // same for readings
+    capture: Mutex<Option<(Capture, String)>> // and the port name to record under
+}
+
+impl Shared {
+    fn record(&self, event: Event) {
+        if let Some((ref capture, ref port)) = *self.capture.lock().unwrap_or_else(PoisonError::into_inner) {
+            capture.record(port, event);
+        }
+    }
+}
+
+pub struct Client<T: Write + Send + 'static> {
+    port: Arc<Mutex<T>>, // shared with the reader, for ACKing pushes
+    timeout: Duration,
+    mode: Mode,
+    seq: u8, // of the last request
+    firmware: Option<Firmware>,
+    shared: Arc<Shared>,
+    incoming: Receiver<io::Result<Incoming>>,
+    pushes: Option<Receiver<Orientation>>,
+    telemetry: Option<Receiver<Sample>>,
+    raw: Option<Receiver<Reading>>,
+    reader: Option<JoinHandle<()>>
+}
+
+impl<T: Write + Send + 'static> Client<T> {
+    // `input` reads from the same port (a try_clone() of it) on a thread of its own. Its reads should time out every 100ms or so,
+    // that's when it gives up on half-received frames and notices the client was dropped.
+    // Starts out in legacy mode, see negotiate().
+    pub fn new(port: T, input: impl Read + Send + 'static, timeout: Duration) -> Self {
+        let port = Arc::new(Mutex::new(port));
+        let shared = Arc::new(Shared::default());
+        let (incoming_tx, incoming) = mpsc::channel();
+        let (pushes_tx, pushes) = mpsc::channel();
+        let (telemetry_tx, telemetry) = mpsc::channel();
+        let (raw_tx, raw) = mpsc::channel();
+
+        let reader = Reader {
+            port: Arc::clone(&port),
+            shared: Arc::clone(&shared),
+            incoming: incoming_tx,
+            pushes: pushes_tx,
+            telemetry: telemetry_tx,
+            raw: raw_tx,
+            decoder: FrameDecoder::default(),
+            push_seq: None
+        };
+        let reader = thread::spawn(move || reader.run(input));
+
+        Client {
+            port, timeout, mode: Mode::Legacy, seq: 0, firmware: None, shared, incoming,
+            pushes: Some(pushes), telemetry: Some(telemetry), raw: Some(raw), reader: Some(reader)
+        }
+    }
+
+    pub fn mode(&self) -> Mode {self.mode}
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.shared.framed.store(mode == Mode::Framed, Ordering::Relaxed);
+        self.shared.record(Event::Mode(mode));
+    }
+
+    // Records every byte sent and received from now on, see capture.rs.
+    pub fn set_capture(&mut self, capture: Capture, port: &str) {
+        *self.shared.capture.lock().unwrap_or_else(PoisonError::into_inner) = Some((capture, port.to_string()));
+        self.shared.record(Event::Mode(self.mode));
+    }
+
+    // None until hello() succeeds, legacy firmware can't say what it is
+    pub fn firmware(&self) -> Option<&Firmware> {self.firmware.as_ref()}
+
+    // Without a HELLO reply, every command is assumed to work, as long as it fits the protocol.
+    pub fn supports(&self, req: Request) -> bool {
+        match self.firmware {
+            Some(ref fw) => fw.supports(req),
+            None => !req.is_framed_only() || self.mode == Mode::Framed
+        }
+    }
+
+    // Bytes skipped while looking for frames, i.e. noise on the line
+    pub fn discarded(&self) -> usize {self.shared.discarded.load(Ordering::Relaxed)}
+
+    // false once reading from or writing to the port failed (e.g. it was unplugged), it has to be reopened
+    pub fn is_connected(&self) -> bool {!self.shared.stopped.load(Ordering::Relaxed)}
+
+    // Orientations pushed by the device (already ACKed, resends filtered out), can only be taken once.
+    // The channel closes when the client is dropped or the port fails.
+    pub fn take_pushes(&mut self) -> Option<Receiver<Orientation>> {self.pushes.take()}
+
+    // Samples streamed by the device, once set_telemetry() turned it on. Can only be taken once, and samples
+    // that arrive before then are dropped.
+    pub fn take_telemetry(&mut self) -> Option<Receiver<Sample>> {
+        self.shared.telemetry.store(true, Ordering::Relaxed);
+        self.telemetry.take()
+    }
+
+    // take_telemetry(), for the readings set_raw() turns on.
+    pub fn take_raw(&mut self) -> Option<Receiver<Reading>> {
+        self.shared.raw.store(true, Ordering::Relaxed);
+        self.raw.take()
+    }
+
+    // Sends SYN inside a frame with seq 0. Framed firmware answers with an ACK frame, legacy firmware skips
+    // the framing bytes (none of them are commands) and answers the SYN with a bare ACK.
+    // The probe is resent every second, in case the device was still in its bootloader.
+    pub fn negotiate(&mut self) -> Result<Mode, Error> {
+        let probe = Frame {seq: 0, payload: Request::Sync.encode()}.encode();
+        self.seq = 0;
+        self.firmware = None;
+        self.set_mode(Mode::Framed); // the reader still hands over bytes outside of frames
+        while self.incoming.try_recv().is_ok() {}
+
+        let deadline = Instant::now() + self.timeout;
+        'probe: loop {
+            self.write(&probe)?;
+
+            let resend = (Instant::now() + PROBE_INTERVAL).min(deadline);
+            loop {
+                match self.next(resend) {
+                    Ok(Incoming::Frame(frame)) if frame.seq == 0 && frame.payload == [ACK] => break 'probe Ok(self.mode),
+                    Ok(Incoming::Byte(ACK)) => {
+                        self.set_mode(Mode::Legacy);
+                        break 'probe Ok(self.mode);
+                    }
+                    Ok(_) => (),
+                    // a push that was being resent when the probe arrived might've taken the probe as its answer
+                    Err(Error::Timeout) if resend < deadline => continue 'probe,
+                    Err(e) => break 'probe Err(e)
+                }
+            }
+        }
+    }
+
+    pub fn send(&mut self, req: Request) -> Result<(), Error> {
+        if !self.supports(req) {return Err(Error::Unsupported(req))}
+        match self.mode {
+            Mode::Legacy => self.write(&req.encode()),
+            Mode::Framed => {
+                self.seq = self.seq.wrapping_add(1);
+                self.write(&Frame {seq: self.seq, payload: req.encode()}.encode())
+            }
+        }
+    }
+
+    pub fn recv(&mut self) -> Result<Response, Error> {
+        Ok(Response::decode(self.recv_payload()?[0]))
+    }
+
+    // recv(), but with the whole payload (always a single byte in legacy mode).
+    // In framed mode, replies to earlier requests (that timed out) are skipped.
+    fn recv_payload(&mut self) -> Result<Vec<u8>, Error> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.next(deadline)? {
+                Incoming::Byte(b) if self.mode == Mode::Legacy => break Ok(vec![b]),
+                Incoming::Frame(frame) if self.mode == Mode::Framed && frame.seq == self.seq => break Ok(frame.payload),
+                _ => ()
+            }
+        }
+    }
+
+    // Asks the firmware what it is. A different major version is refused with Error::Incompatible,
+    // otherwise commands it doesn't list fail with Error::Unsupported from then on.
+    pub fn hello(&mut self) -> Result<&Firmware, Error> {
+        self.send(Request::Hello)?;
+        let payload = self.recv_payload()?;
+        let fw = Firmware::decode(&payload).ok_or(Error::Unexpected(Response::decode(payload[0])))?;
+        if fw.version.0 != FIRMWARE_MAJOR {return Err(Error::Incompatible(fw))}
+        Ok(self.firmware.insert(fw))
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.send(Request::Sync)?;
+        match self.recv()? {
+            Response::Ack => Ok(()),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    // true if autorotation is running
+    pub fn query(&mut self) -> Result<bool, Error> {
+        self.send(Request::Query)?;
+        match self.recv()? {
+            Response::Ack => Ok(true),
+            Response::Nak => Ok(false),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    // true if autorotation was started, false if it was already running
+    pub fn start(&mut self, orientation: Orientation, threshold: u8) -> Result<bool, Error> {
+        self.send(Request::Start)?;
+        match self.recv()? {
+            Response::Enq => {
+                self.send(Request::Settings {orientation, threshold})?;
+                match self.recv()? {
+                    Response::Ack => Ok(true),
+                    r => Err(Error::Unexpected(r))
+                }
+            }
+            Response::Nak => Ok(false),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    // true if autorotation was stopped, false if it wasn't running
+    pub fn stop(&mut self) -> Result<bool, Error> {
+        self.send(Request::Stop)?;
+        match self.recv()? {
+            Response::Ack => Ok(true),
+            Response::Nak => Ok(false),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    pub fn recalibrate(&mut self) -> Result<(), Error> {
+        self.send(Request::Recalibrate)?;
+        match self.recv()? {
+            Response::Ack => Ok(()),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    pub fn get_tuning(&mut self) -> Result<Tuning, Error> {
+        self.send(Request::GetTuning)?;
+        let payload = self.recv_payload()?;
+        match *payload {
+            [SO, ref tuning @ ..] => Tuning::decode(tuning).ok_or(Error::Unexpected(Response::decode(SO))),
+            _ => Err(Error::Unexpected(Response::decode(payload[0])))
+        }
+    }
+
+    // true if the firmware took it, false if it was out of range
+    pub fn set_tuning(&mut self, tuning: Tuning) -> Result<bool, Error> {
+        self.send(Request::SetTuning(tuning))?;
+        match self.recv()? {
+            Response::Ack => Ok(true),
+            Response::Nak => Ok(false),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    // Streams a sample every interval_ms, 0 stops it.
+    pub fn set_telemetry(&mut self, interval_ms: u16) -> Result<(), Error> {
+        self.send(Request::Telemetry(interval_ms))?;
+        match self.recv()? {
+            Response::Ack => Ok(()),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    // Streams a reading every interval_ms, 0 stops it.
+    pub fn set_raw(&mut self, interval_ms: u16) -> Result<(), Error> {
+        self.send(Request::Raw(interval_ms))?;
+        match self.recv()? {
+            Response::Ack => Ok(()),
+            r => Err(Error::Unexpected(r))
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut port = self.port.lock().unwrap_or_else(PoisonError::into_inner);
+        self.shared.record(Event::Sent(bytes.to_vec()));
+        let result = port.write_all(bytes).and_then(|()| port.flush());
+        if result.is_err() {self.shared.stopped.store(true, Ordering::Relaxed)}
+        Ok(result?)
+    }
+
+    fn next(&mut self, deadline: Instant) -> Result<Incoming, Error> {
+        match self.incoming.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Ok(incoming)) => Ok(incoming),
+            Ok(Err(e)) => Err(Error::Io(e)),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Disconnected)
+        }
+    }
+}
+
+// The reader has to let go of its handle before the port can be reopened.
+impl<T: Write + Send + 'static> Drop for Client<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {_=reader.join()}
+    }
+}
+
+struct Reader<T: Write> {
+    port: Arc<Mutex<T>>,
+    shared: Arc<Shared>,
+    incoming: Sender<io::Result<Incoming>>,
+    pushes: Sender<Orientation>,
+    telemetry: Sender<Sample>,
+    raw: Sender<Reading>,
+    decoder: FrameDecoder,
+    push_seq: Option<u8> // of the last push, the device resends it until it gets ACKed
+}
+
+impl<T: Write> Reader<T> {
+    // Until the client is dropped or a read fails. The error is handed to whoever's waiting for a reply.
+    // Sends to the client's channels can fail once it's gone, that's noticed through `closed` instead.
+    fn run(mut self, mut input: impl Read) {
+        let mut buffer = [0u8; MAX_PAYLOAD];
+        while !self.shared.closed.load(Ordering::Relaxed) && !self.shared.stopped.load(Ordering::Relaxed) {
+            let result = match input.read(&mut buffer) {
+                Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    self.shared.record(Event::Received(buffer[..n].to_vec()));
+                    buffer[..n].iter().try_for_each(|&b| self.byte(b))
+                }
+                // the line went quiet, so a partial frame isn't going to be finished
+                Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {
+                    self.decoder.flush();
+                    self.frames()
+                }
+                Err(e) => Err(e)
+            };
+            if let Err(e) = result {
+                _=self.incoming.send(Err(e));
+                break;
+            }
+        }
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn byte(&mut self, b: u8) -> io::Result<()> {
+        if !self.shared.framed.load(Ordering::Relaxed) {
+            if let Response::Orientation(o) = Response::decode(b) {return self.push(None, o)}
+            _=self.incoming.send(Ok(Incoming::Byte(b)));
+            return Ok(());
+        }
+
+        let idle = self.decoder.is_idle();
+        self.decoder.push(b);
+        if idle && b != STX {_=self.incoming.send(Ok(Incoming::Byte(b)));}
+        self.frames()
+    }
+
+    fn frames(&mut self) -> io::Result<()> {
+        self.shared.discarded.store(self.decoder.discarded, Ordering::Relaxed);
+        while let Some(frame) = self.decoder.pop() {
+            match frame.payload.first().map(|&b| Response::decode(b)) {
+                Some(Response::Orientation(o)) => self.push(Some(frame.seq), o)?,
+                // nobody's listening, and they'd only get in the way of the replies
+                Some(Response::Unknown(DLE)) if !self.shared.telemetry.load(Ordering::Relaxed) => (),
+                Some(Response::Unknown(DLE)) => if let Some(sample) = Sample::decode(&frame.payload) {_=self.telemetry.send(sample)}
+                Some(Response::Unknown(RS)) if !self.shared.raw.load(Ordering::Relaxed) => (),
+                Some(Response::Unknown(RS)) => if let Some(reading) = Reading::decode(&frame.payload) {_=self.raw.send(reading)}
+                Some(_) => _=self.incoming.send(Ok(Incoming::Frame(frame))),
+                None => () // empty
+            }
+        }
+        Ok(())
+    }
+
+    // ACKs the push, and passes it on unless it's a resend of one that was already taken. seq is None in legacy mode.
+    fn push(&mut self, seq: Option<u8>, o: Orientation) -> io::Result<()> {
+        let ack = match seq {
+            Some(seq) => Frame {seq, payload: Request::Ack.encode()}.encode(),
+            None => Request::Ack.encode()
+        };
+        {
+            let mut port = self.port.lock().unwrap_or_else(PoisonError::into_inner);
+            self.shared.record(Event::Sent(ack.clone()));
+            port.write_all(&ack)?;
+            port.flush()?;
+        }
+
+        let new = seq.is_none() || seq != self.push_seq;
+        self.push_seq = seq;
+        if new {_=self.pushes.send(o)} // nobody might be listening, that's fine
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Orientation::*;
+
+    // One way of an in-memory serial line, reads time out like a port's would.
+    struct Line(Receiver<Vec<u8>>, Vec<u8>);
+    impl Read for Line {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.1.is_empty() {
+                match self.0.recv_timeout(Duration::from_millis(20)) {
+                    Ok(bytes) => self.1 = bytes,
+                    Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                    Err(RecvTimeoutError::Disconnected) => return Ok(0)
+                }
+            }
+            let n = buf.len().min(self.1.len());
+            buf[..n].copy_from_slice(&self.1[..n]);
+            self.1.drain(..n);
+            Ok(n)
+        }
+    }
+    // and the other way, every write_all() ends up as one message
+    struct Wire(Sender<Vec<u8>>);
+    impl Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.send(buf.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {Ok(())}
+    }
+
+    // A client, what it writes, and where to put what the device says
+    fn client(timeout: Duration) -> (Client<Wire>, Receiver<Vec<u8>>, Sender<Vec<u8>>) {
+        let (wire, sent) = mpsc::channel();
+        let (answers, line) = mpsc::channel();
+        (Client::new(Wire(wire), Line(line, Vec::new()), timeout), sent, answers)
+    }
+
+    // Plays the device until the client is dropped, answering every write. Hands back everything that was written.
+    fn device(sent: Receiver<Vec<u8>>, answers: Sender<Vec<u8>>, mut answer: impl FnMut(&[u8]) -> Vec<u8> + Send + 'static) -> JoinHandle<Vec<Vec<u8>>> {
+        thread::spawn(move || sent.iter().inspect(|bytes| _=answers.send(answer(bytes))).collect())
+    }
+
+    // The frame in bytes, which have to be exactly one
+    fn unframe(bytes: &[u8]) -> Frame {
+        let mut decoder = FrameDecoder::default();
+        bytes.iter().for_each(|&b| decoder.push(b));
+        let frame = decoder.pop().expect("a frame");
+        assert!(decoder.pop().is_none() && decoder.is_idle() && decoder.discarded == 0, "{bytes:02x?}");
+        frame
+    }
+
+    // What the firmware does with a payload, as far as autorotation goes
+    #[derive(Default)]
+    struct Fake {running: bool, settings: bool}
+    impl Fake {
+        fn answer(&mut self, payload: &[u8]) -> Vec<u8> {
+            if std::mem::take(&mut self.settings) {
+                self.running = true;
+                return vec![ACK];
+            }
+            match Request::decode_payload(payload) {
+                Some(Request::Sync) => vec![ACK],
+                Some(Request::Query) => vec![if self.running {ACK} else {NAK}],
+                Some(Request::Start) if self.running => vec![NAK],
+                Some(Request::Start) => {
+                    self.settings = true;
+                    vec![ENQ]
+                }
+                Some(Request::Stop) => vec![if std::mem::take(&mut self.running) {ACK} else {NAK}],
+                _ => vec![NAK]
+            }
+        }
+    }
+
+    fn talk(mode: Mode) -> Vec<Vec<u8>> {
+        let (mut client, sent, answers) = client(Duration::from_secs(1));
+        client.set_mode(mode);
+        let mut fake = Fake::default();
+        let device = device(sent, answers, move |bytes| match mode {
+            Mode::Legacy => fake.answer(bytes),
+            Mode::Framed => {
+                let frame = unframe(bytes);
+                Frame {seq: frame.seq, payload: fake.answer(&frame.payload)}.encode()
+            }
+        });
+
+        client.sync().unwrap();
+        assert!(!client.query().unwrap());
+        assert!(client.start(PortraitFlipped, 40).unwrap());
+        assert!(client.query().unwrap());
+        assert!(!client.start(PortraitFlipped, 40).unwrap()); // already running
+        assert!(client.stop().unwrap());
+        assert!(!client.stop().unwrap());
+        drop(client);
+        device.join().unwrap()
+    }
+    const CONVERSATION: [&[u8]; 8] = [&[SYN], &[ENQ], &[DC1], &[3, 40], &[ENQ], &[DC1], &[DC2], &[DC2]];
+
+    #[test]
+    fn talks_legacy() {
+        assert_eq!(talk(Mode::Legacy), CONVERSATION);
+    }
+
+    #[test]
+    fn talks_framed() {
+        let sent = talk(Mode::Framed);
+        assert_eq!(sent.len(), CONVERSATION.len());
+        for (seq, (bytes, payload)) in (1..).zip(sent.iter().zip(CONVERSATION)) {
+            assert_eq!(unframe(bytes), Frame {seq, payload: payload.to_vec()});
+        }
+    }
+
+    #[test]
+    fn skips_stale_replies() {
+        let (mut client, sent, answers) = client(Duration::from_secs(1));
+        client.set_mode(Mode::Framed);
+        // the reply to a request that timed out shows up late
+        let device = device(sent, answers, |bytes| {
+            let frame = unframe(bytes);
+            [Frame {seq: frame.seq.wrapping_sub(1), payload: vec![NAK]}.encode(), Frame {seq: frame.seq, payload: vec![ACK]}.encode()].concat()
+        });
+        assert!(client.query().unwrap());
+        drop(client);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn acks_pushes_legacy() {
+        let (mut client, sent, answers) = client(Duration::from_secs(1));
+        let pushes = client.take_pushes().unwrap();
+        // a push that crossed the SYN
+        let device = device(sent, answers, |bytes| match bytes {
+            [SYN] => vec![Response::Orientation(Portrait).encode(), ACK],
+            _ => vec![]
+        });
+        client.sync().unwrap();
+        assert_eq!(pushes.recv_timeout(Duration::from_secs(1)), Ok(Portrait));
+        drop(client);
+        assert_eq!(device.join().unwrap(), [[SYN], [ACK]]);
+    }
+
+    #[test]
+    fn acks_pushes_framed() {
+        let (mut client, sent, answers) = client(Duration::from_secs(1));
+        client.set_mode(Mode::Framed);
+        let pushes = client.take_pushes().unwrap();
+        let push = |seq, o| Frame {seq, payload: vec![Response::Orientation(o).encode()]}.encode();
+
+        // the ACK to 7 got lost so it's resent, and noise that looks like the start of a long frame swallows 8
+        // until the line goes quiet
+        for bytes in [push(7, Portrait), push(7, Portrait), [&[STX, VERSION, 0, 40][..], &push(8, LandscapeFlipped)].concat()] {
+            answers.send(bytes).unwrap();
+        }
+        let timeout = Duration::from_secs(1);
+        assert_eq!(pushes.recv_timeout(timeout), Ok(Portrait));
+        assert_eq!(pushes.recv_timeout(timeout), Ok(LandscapeFlipped));
+        for seq in [7, 7, 8] {
+            assert_eq!(sent.recv_timeout(timeout).map(|bytes| unframe(&bytes)), Ok(Frame {seq, payload: vec![ACK]}));
+        }
+        assert_eq!(client.discarded(), 4);
+
+        // a new push can reuse a seq, as long as it's not the last one
+        answers.send(push(9, Landscape)).unwrap();
+        answers.send(push(7, Portrait)).unwrap();
+        assert_eq!(pushes.recv_timeout(timeout), Ok(Landscape));
+        assert_eq!(pushes.recv_timeout(timeout), Ok(Portrait));
+        assert!(pushes.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn negotiates_legacy() {
+        let (mut client, sent, answers) = client(Duration::from_millis(500));
+        // legacy firmware skips the framing and answers the SYN in it
+        let device = device(sent, answers, |bytes| if bytes.contains(&SYN) {vec![ACK]} else {vec![]});
+        assert_eq!(client.negotiate().unwrap(), Mode::Legacy);
+        assert_eq!(client.mode(), Mode::Legacy);
+        assert!(client.supports(Request::Sync) && !client.supports(Request::Hello));
+        client.sync().unwrap();
+        drop(client);
+        assert_eq!(device.join().unwrap()[1], [SYN]);
+    }
+
+    #[test]
+    fn negotiates_framed_without_hello() {
+        let (mut client, sent, answers) = client(Duration::from_millis(300));
+        // framed firmware too old to answer HELLO
+        let device = device(sent, answers, |bytes| {
+            let frame = unframe(bytes);
+            match *frame.payload {
+                [SYN] => Frame {seq: frame.seq, payload: vec![ACK]}.encode(),
+                _ => vec![]
+            }
+        });
+        assert_eq!(client.negotiate().unwrap(), Mode::Framed);
+        assert!(matches!(client.hello(), Err(Error::Timeout)));
+        assert!(client.firmware().is_none());
+        assert!(client.supports(Request::GetTuning)); // trusted with everything
+        client.sync().unwrap();
+        drop(client);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn resends_probes() {
+        let (mut client, sent, answers) = client(Duration::from_millis(1500));
+        // still in the bootloader for the first one
+        let mut probes = 0;
+        let device = device(sent, answers, move |_| {
+            probes += 1;
+            if probes > 1 {vec![ACK]} else {vec![]}
+        });
+        assert_eq!(client.negotiate().unwrap(), Mode::Legacy);
+        drop(client);
+        assert_eq!(device.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_negotiating() {
+        let (mut client, sent, answers) = client(Duration::from_millis(200));
+        let device = device(sent, answers, |_| vec![]);
+        assert!(matches!(client.negotiate(), Err(Error::Timeout)));
+        drop(client);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn decoder_drops_bad_frames() {
+        let good = Frame {seq: 3, payload: vec![ACK]};
+        let mut bad_crc = good.encode();
+        *bad_crc.last_mut().unwrap() ^= 1;
+        let mut bad_version = good.encode();
+        bad_version[1] = VERSION + 1;
+        let too_long = [STX, VERSION, 0, MAX_PAYLOAD as u8 + 1];
+
+        for bad in [&bad_crc[..], &bad_version, &too_long] {
+            let mut decoder = FrameDecoder::default();
+            bad.iter().chain(&good.encode()).for_each(|&b| decoder.push(b));
+            assert_eq!(decoder.pop(), Some(good.clone()), "{bad:02x?}");
+            assert_eq!(decoder.pop(), None);
+            assert!(decoder.is_idle() && decoder.discarded > 0);
+        }
+    }
+
+    #[test]
+    fn decoder_resyncs() {
+        let frames = [Frame {seq: 1, payload: vec![ENQ]}, Frame {seq: 2, payload: b"\x02\x02\x02".to_vec()}];
+        let mut decoder = FrameDecoder::default();
+        // STXs in the noise, one of them right before the frame
+        for &b in [&[0x55, STX, 0x13, 0xaa, STX][..], &frames[0].encode(), &[STX], &frames[1].encode()].concat().iter() {
+            decoder.push(b);
+        }
+        assert_eq!(decoder.pop(), Some(frames[0].clone()));
+        assert_eq!(decoder.pop(), Some(frames[1].clone()));
+        assert_eq!(decoder.discarded, 6);
+
+        // a length that swallows the next frame, only given up on once the line goes quiet
+        let mut decoder = FrameDecoder::default();
+        [STX, VERSION, 0, 40].iter().chain(&frames[0].encode()).for_each(|&b| decoder.push(b));
+        assert_eq!(decoder.pop(), None);
+        assert!(!decoder.is_idle());
+        decoder.flush();
+        assert_eq!(decoder.pop(), Some(frames[0].clone()));
+        assert!(decoder.is_idle());
+        assert_eq!(decoder.discarded, 4);
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let sample = Sample {millis: 0x01020304, deviation: -12.5, offset: 90.0};
+        let bytes = sample.encode();
+        assert_eq!(bytes, [&[DLE, 1, 2, 3, 4][..], &(-12.5f32).to_be_bytes(), &90f32.to_be_bytes()].concat());
+        assert_eq!(Sample::decode(&bytes), Some(sample));
+        assert_eq!(Sample::decode(&bytes[..12]), None);
+        assert_eq!(Sample::decode(&[&bytes[..], &[0]].concat()), None);
+        assert_eq!(Sample::decode(&[&[RS][..], &bytes[1..]].concat()), None);
+        assert_eq!(sample.angle(), -102.5);
+    }
+
+    #[test]
+    fn readings_round_trip() {
+        let reading = Reading {millis: 0x01020304, x: 0.5, y: -0.25, z: 1.0};
+        let bytes = reading.encode();
+        assert_eq!(bytes, [&[RS, 1, 2, 3, 4][..], &0.5f32.to_be_bytes(), &(-0.25f32).to_be_bytes(), &1f32.to_be_bytes()].concat());
+        assert_eq!(Reading::decode(&bytes), Some(reading));
+        assert_eq!(Reading::decode(&bytes[..16]), None);
+        assert_eq!(Reading::decode(&[&bytes[..], &[0]].concat()), None);
+        assert_eq!(Reading::decode(&[&[DLE][..], &bytes[1..]].concat()), None);
+        assert!((Reading {millis: 0, x: 1.0, y: 0.0, z: 0.0}.deviation() - 90.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn hello_payloads() {
+        let fw = Firmware {name: "monitor-autorotate".to_string(), version: (1, 4, 2), commands: vec![SYN, ENQ, DC1, DC2, SOH, SI, 0x7f]};
+        let payload = fw.encode();
+        assert_eq!(payload[..12], [SOH, 1, 4, 2, 7, SYN, ENQ, DC1, DC2, SOH, SI, 0x7f]);
+        assert_eq!(&payload[12..], b"monitor-autorotate");
+        assert_eq!(Firmware::decode(&payload), Some(fw.clone()));
+        assert_eq!(fw.to_string(), "monitor-autorotate v1.4.2");
+        assert_eq!(fw.command_names(), "SYN ENQ DC1 DC2 SOH SI 0x7f");
+        assert!(fw.supports(Request::Query) && fw.supports(Request::Settings {orientation: Portrait, threshold: 65}) && fw.supports(Request::Ack));
+        assert!(!fw.supports(Request::Recalibrate) && !fw.supports(Request::GetTuning));
+
+        let empty = Firmware::decode(&[SOH, 0, 1, 0, 0]).unwrap(); // no commands, no name
+        assert_eq!((empty.name.as_str(), empty.commands.len()), ("", 0));
+        assert_eq!(Firmware::decode(&[SOH, 1, 0, 0, 3, SYN, ENQ]), None); // fewer commands than it says
+        assert_eq!(Firmware::decode(&[SOH, 1, 0, 0]), None);
+        assert_eq!(Firmware::decode(&[ACK, 1, 0, 0, 0]), None);
+    }
+
+    // framed firmware that answers HELLO with `fw`, and SYN
+    fn hello_device(fw: Firmware) -> (Client<Wire>, JoinHandle<Vec<Vec<u8>>>) {
+        let (mut client, sent, answers) = client(Duration::from_millis(300));
+        client.set_mode(Mode::Framed);
+        let device = device(sent, answers, move |bytes| {
+            let frame = unframe(bytes);
+            let payload = match *frame.payload {
+                [SOH] => fw.encode(),
+                [SYN] => vec![ACK],
+                _ => vec![NAK]
+            };
+            Frame {seq: frame.seq, payload}.encode()
+        });
+        (client, device)
+    }
+
+    #[test]
+    fn refuses_incompatible_firmware() {
+        let fw = Firmware {name: "monitor-autorotate".to_string(), version: (FIRMWARE_MAJOR + 1, 0, 0), commands: vec![SYN, SOH]};
+        let (mut client, device) = hello_device(fw.clone());
+        assert!(matches!(client.hello(), Err(Error::Incompatible(ref incompatible)) if *incompatible == fw));
+        assert!(client.firmware().is_none());
+        drop(client);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn only_sends_what_hello_listed() {
+        let fw = Firmware {name: "monitor-autorotate".to_string(), version: (FIRMWARE_MAJOR, 0, 0), commands: vec![SYN, SOH]};
+        let (mut client, device) = hello_device(fw.clone());
+        assert_eq!(client.hello().unwrap(), &fw);
+        assert_eq!(client.firmware(), Some(&fw));
+        assert!(client.supports(Request::Sync) && !client.supports(Request::Query));
+        client.sync().unwrap();
+        assert!(matches!(client.query(), Err(Error::Unsupported(Request::Query))));
+        assert!(matches!(client.recalibrate(), Err(Error::Unsupported(Request::Recalibrate))));
+        drop(client);
+        let sent: Vec<_> = device.join().unwrap().iter().map(|bytes| unframe(bytes).payload).collect();
+        assert_eq!(sent, [[SOH], [SYN]]); // the rest never went out
+    }
+
+    #[test]
+    fn tunings_round_trip() {
+        let tuning = Tuning {threshold: 65, hysteresis: 5, debounce_ms: 0x0102};
+        assert_eq!(tuning.encode(), [65, 5, 1, 2]);
+        assert_eq!(Tuning::decode(&tuning.encode()), Some(tuning));
+        assert_eq!(Tuning::decode(&[65, 5, 1]), None);
+        assert_eq!(Tuning::decode(&[65, 5, 1, 2, 3]), None);
+        let request = Request::SetTuning(tuning);
+        assert_eq!(request.encode(), [SI, 65, 5, 1, 2]);
+        assert_eq!(Request::decode_payload(&request.encode()), Some(request));
+        assert_eq!(Request::decode_payload(&[SI, 65, 5]), None);
+    }
+
+    #[test]
+    fn rejects_tunings_out_of_range() {
+        let tuning = |threshold, hysteresis| Tuning {threshold, hysteresis, debounce_ms: 0};
+        for (threshold, hysteresis) in [(65, 5), (1, 0), (89, 88)] {
+            assert!(tuning(threshold, hysteresis).validate().is_ok(), "{threshold} {hysteresis}");
+        }
+        for (threshold, hysteresis) in [(0, 0), (90, 5), (255, 5), (65, 65), (10, 20)] {
+            assert!(tuning(threshold, hysteresis).validate().is_err(), "{threshold} {hysteresis}");
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::{io::{self, Read, Write}, thread, time::{Duration, Instant}};
+use crate::{orientation::Orientation, tracker::Tracker};
+use crate::protocol::{self, Request, Response, Frame, Firmware, Tuning, Sample, Reading, ACK, DC1, DC2, DC3, ENQ, SOH, SO, SI, DLE, RS, SYN, STX, VERSION};
+
+// Emulates arduino/monitor-autorotate/monitor-autorotate.ino, one loop() per step(). Speaks both the legacy and the framed protocol.
+// The port should have a short read timeout, a read that times out is treated like Serial.available() == 0.
+pub struct Device<T: Read + Write> {
+    port: T,
+    pub running: bool,
+    pub tracker: Tracker, // current_ori, offset and the tuning
+    pub telemetry: Duration, // between samples, zero while it's off
+    last_sample: Instant,
+    pub raw: Duration, // between readings, zero while they're off
+    last_reading: Instant,
+    booted: Instant, // for the samples' millis()
+    pub tilt: f32, // what deviation(x, y) would return for the current IMU reading
+    pub resend_interval: Duration,
+    pub firmware: Firmware, // what it answers HELLO with
+    pub framed: bool, // whether the last command came in a frame, replies and pushes go out the same way
+    seq_in: u8,
+    seq_out: u8
+}
+
+impl<T: Read + Write> Device<T> {
+    pub fn new(port: T) -> Self {
+        Device {
+            port,
+            running: false,
+            tracker: Tracker::new(Orientation::Landscape, 0.0, Tuning {threshold: 65, hysteresis: 5, debounce_ms: 0}),
+            telemetry: Duration::ZERO,
+            last_sample: Instant::now(),
+            raw: Duration::ZERO,
+            last_reading: Instant::now(),
+            booted: Instant::now(),
+            tilt: 0.0,
+            resend_interval: Duration::from_millis(100),
+            firmware: Firmware {
+                name: "monitor-autorotate (simulated)".to_string(),
+                version: (1, 4, 0),
+                commands: vec![SYN, ENQ, DC1, DC2, DC3, SOH, SO, SI, DLE, RS]
+            },
+            framed: false,
+            seq_in: 0,
+            seq_out: 0
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {&self.port}
+    pub fn get_mut(&mut self) -> &mut T {&mut self.port}
+
+    pub fn step(&mut self) -> io::Result<()> {
+        if self.running {self.update_orientation()?;}
+        if !self.telemetry.is_zero() && self.last_sample.elapsed() >= self.telemetry {
+            self.last_sample = Instant::now();
+            let sample = Sample {millis: self.booted.elapsed().as_millis() as u32, deviation: self.tilt, offset: self.tracker.offset};
+            self.port.write_all(&Frame {seq: 0, payload: sample.encode()}.encode())?;
+            self.port.flush()?;
+        }
+        if !self.raw.is_zero() && self.last_reading.elapsed() >= self.raw {
+            self.last_reading = Instant::now();
+            // 1g pointing `tilt` away from down
+            let (x, y) = self.tilt.to_radians().sin_cos();
+            let reading = Reading {millis: self.booted.elapsed().as_millis() as u32, x, y, z: 0.0};
+            self.port.write_all(&Frame {seq: 0, payload: reading.encode()}.encode())?;
+            self.port.flush()?;
+        }
+
+        if let Some(b) = self.try_read()? {
+            self.framed = false;
+            let mut payload = vec![b];
+            if b == STX {
+                match self.read_frame()? {
+                    Some(p) if !p.is_empty() => {
+                        self.framed = true;
+                        payload = p;
+                    }
+                    _ => return Ok(())
+                }
+            }
+
+            match Request::decode_payload(&payload) {
+                Some(Request::Start) => if self.running {self.send(Response::Nak)?} else {
+                    self.send(Response::Enq)?;
+                    if self.framed {
+                        let settings = loop {
+                            if self.read_blocking()? != STX {continue}
+                            if let Some(payload) = self.read_frame()? {
+                                if payload.len() == 2 {break payload}
+                            }
+                        };
+                        self.start(settings[0], settings[1]);
+                    } else {
+                        let (ori, threshold) = (self.read_blocking()?, self.read_blocking()?);
+                        self.start(ori, threshold);
+                    }
+                    self.running = true;
+                    self.send(Response::Ack)?;
+                }
+                Some(Request::Stop) => if self.running {
+                    self.running = false;
+                    self.send(Response::Ack)?;
+                } else {self.send(Response::Nak)?}
+                Some(Request::Recalibrate) => {
+                    self.send(Response::Ack)?;
+                    self.calibrate();
+                }
+                Some(Request::Query) => self.send(if self.running {Response::Ack} else {Response::Nak})?,
+                Some(Request::Sync) => self.send(Response::Ack)?,
+                Some(Request::Hello) if self.framed => self.send_payload(self.firmware.encode())?,
+                Some(Request::GetTuning) if self.framed => {
+                    let tuning = self.tuning();
+                    self.send_payload([&[SO][..], &tuning.encode()].concat())?;
+                }
+                Some(Request::SetTuning(tuning)) if self.framed => if tuning.validate().is_ok() {
+                    self.set_tuning(tuning);
+                    self.send(Response::Ack)?;
+                } else {self.send(Response::Nak)?}
+                Some(Request::Telemetry(interval_ms)) if self.framed => {
+                    self.telemetry = Duration::from_millis(interval_ms as u64);
+                    self.send(Response::Ack)?;
+                }
+                Some(Request::Raw(interval_ms)) if self.framed => {
+                    self.raw = Duration::from_millis(interval_ms as u64);
+                    self.send(Response::Ack)?;
+                }
+                // the right command byte with a payload that doesn't fit
+                None if self.framed && matches!(payload[0], SI | DLE | RS) => self.send(Response::Nak)?,
+                _ => ()
+            }
+        }
+        Ok(())
+    }
+
+    pub fn calibrate(&mut self) {
+        self.tracker.offset = self.tilt;
+    }
+
+    pub fn tuning(&self) -> Tuning {self.tracker.tuning}
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tracker.tuning = tuning;
+    }
+
+    // DC1's settings. The firmware would take an orientation past 3 as is, there's no telling what it'd do with it.
+    fn start(&mut self, ori: u8, threshold: u8) {
+        self.tracker.reset(Orientation::from_u8(ori).unwrap_or(Orientation::Landscape));
+        self.tracker.tuning.threshold = threshold;
+    }
+
+    fn update_orientation(&mut self) -> io::Result<()> {
+        if self.tracker.update(self.tilt, Instant::now()).is_some() {self.change_ori()?;}
+        Ok(())
+    }
+
+    // serial_changeori(): resend the orientation until the host answers, true if the answer was ACK
+    fn change_ori(&mut self) -> io::Result<bool> {
+        let seq = self.seq_out;
+        self.seq_out = self.seq_out.wrapping_add(1);
+        let code = DC1 + self.tracker.orientation as u8;
+        let push = if self.framed {Frame {seq, payload: vec![code]}.encode()} else {vec![code]};
+        self.port.write_all(&push)?;
+        self.port.flush()?;
+        thread::sleep(self.resend_interval);
+
+        loop {
+            if let Some(b) = self.try_read()? {
+                if !self.framed {break Ok(b == ACK)}
+                break Ok(b == STX && self.read_frame()?.is_some_and(|p| p == [ACK]) && self.seq_in == seq);
+            }
+            thread::sleep(self.resend_interval);
+            self.port.write_all(&push)?;
+            self.port.flush()?;
+        }
+    }
+
+    fn send(&mut self, r: Response) -> io::Result<()> {
+        self.send_payload(vec![r.encode()])
+    }
+
+    // Only single bytes fit the legacy protocol.
+    fn send_payload(&mut self, payload: Vec<u8>) -> io::Result<()> {
+        if self.framed {
+            self.port.write_all(&Frame {seq: self.seq_in, payload}.encode())?;
+        } else {
+            self.port.write_all(&payload[..1])?;
+        }
+        self.port.flush()
+    }
+
+    // read_frame(): the rest of a frame after its STX, None if it didn't check out.
+    // Like the firmware, gives up if the next byte takes longer than 100ms.
+    fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 3]; // version, seq, length
+        for b in header.iter_mut() {
+            match self.read_timeout()? {Some(v) => *b = v, None => return Ok(None)}
+        }
+        if header[0] != VERSION {return Ok(None)}
+
+        let mut body = header.to_vec();
+        for _ in 0..header[2] as usize + 2 {
+            match self.read_timeout()? {Some(v) => body.push(v), None => return Ok(None)}
+        }
+        let crc = body.split_off(body.len() - 2);
+        if protocol::crc16(&body) != u16::from_be_bytes([crc[0], crc[1]]) {return Ok(None)}
+
+        self.seq_in = header[1];
+        Ok(Some(body.split_off(3)))
+    }
+
+    fn read_timeout(&mut self) -> io::Result<Option<u8>> {
+        let deadline = Instant::now() + Duration::from_millis(100);
+        loop {
+            if let Some(b) = self.try_read()? {break Ok(Some(b))}
+            if Instant::now() >= deadline {break Ok(None)}
+        }
+    }
+
+    fn try_read(&mut self) -> io::Result<Option<u8>> {
+        let mut buffer = [0u8];
+        match self.port.read(&mut buffer) {
+            Ok(1) => Ok(Some(buffer[0])),
+            Ok(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn read_blocking(&mut self) -> io::Result<u8> {
+        loop {
+            if let Some(b) = self.try_read()? {break Ok(b)}
+        }
+    }
+}
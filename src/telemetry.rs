@@ -0,0 +1,181 @@
+use std::{collections::VecDeque, fs::File, io::{self, BufWriter, Write}, path::Path, sync::mpsc::RecvTimeoutError, time::Duration};
+use chrono::{Local, NaiveDateTime};
+use crossterm::{cursor, event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, style::{Color, Print, ResetColor, SetForegroundColor}, terminal::{self, ClearType}, queue};
+use everythingdoer::{protocol::{Client, Request, Sample, Tuning}, error, warn, info};
+use crate::{Port, serial_send};
+
+// Streams samples into a graph of the angle (newest on the right) until q/esc/ctrl+c, and into `csv` if given.
+// `tuning` is what the lines get drawn at if the firmware can't be asked.
+pub fn run(client: &mut Client<Port>, port: &str, interval_ms: u16, csv: Option<&Path>, tuning: Tuning) -> bool {
+    let tuning = match client.supports(Request::GetTuning).then(|| client.get_tuning()) {
+        Some(Ok(tuning)) => tuning,
+        Some(Err(e)) => {
+            warn!(Serial, "Couldn't get the tuning from `{port}`, drawing `{tuning}` instead: {e}");
+            tuning
+        }
+        None => tuning
+    };
+    let mut csv = match csv.map(|path| File::create(path).map(|f| (path, BufWriter::new(f)))).transpose() {
+        Ok(csv) => csv,
+        Err(e) => {
+            error!(Serial, "Couldn't create `{}`: {e}", csv.unwrap().display());
+            return false;
+        }
+    };
+    if let Some((_, ref mut f)) = csv {_=writeln!(f, "{CSV_HEADER}")}
+
+    let samples = match client.take_telemetry() {
+        Some(samples) => samples,
+        None => return false
+    };
+    if serial_send(Request::Telemetry(interval_ms), || client.set_telemetry(interval_ms)).is_none() {return false}
+
+    if let Err(e) = terminal::enable_raw_mode() {
+        error!(Serial, "Couldn't put the terminal in raw mode: {e}");
+        _=client.set_telemetry(0);
+        return false;
+    }
+    let mut stdout = io::stdout();
+    _=queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide);
+
+    let mut graph = Graph {port, tuning, samples: VecDeque::new()};
+    let mut received = 0;
+    let ret = loop {
+        match samples.recv_timeout(Duration::from_millis(50)) {
+            // and whatever else is waiting, it only gets drawn once
+            Ok(first) => {
+                for sample in std::iter::once(first).chain(samples.try_iter()) {
+                    received += 1;
+                    if let Some((_, ref mut f)) = csv {
+                        _=writeln!(f, "{}", csv_row(Local::now().naive_local(), &sample));
+                    }
+                    graph.samples.push_back(sample);
+                }
+                _=graph.draw(&mut stdout);
+            }
+            Err(RecvTimeoutError::Timeout) => if !client.is_connected() {break Err("The port stopped responding.".to_string())},
+            Err(RecvTimeoutError::Disconnected) => break Err("The port stopped responding.".to_string())
+        }
+
+        match quit_pressed() {
+            Ok(false) => (),
+            Ok(true) => break Ok(()),
+            Err(e) => break Err(e.to_string())
+        }
+    };
+
+    _=queue!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    _=stdout.flush();
+    _=terminal::disable_raw_mode();
+
+    if let Err(e) = client.set_telemetry(0) {error!(Serial, "Couldn't stop the telemetry on `{port}`: {e}")}
+    if let Some((path, mut f)) = csv {
+        match f.flush() {
+            Ok(()) => info!(Serial, "Wrote `{received}` samples to `{}`.", path.display()),
+            Err(e) => error!(Serial, "Couldn't write to `{}`: {e}", path.display())
+        }
+    }
+    match ret {
+        Ok(()) => true,
+        Err(e) => {
+            error!(Serial, "`{port}`: {e}");
+            false
+        }
+    }
+}
+
+const CSV_HEADER: &str = "time,device_ms,deviation,offset,angle";
+
+fn csv_row(time: NaiveDateTime, sample: &Sample) -> String {
+    format!("{},{},{},{},{}", time.format("%Y-%m-%d %H:%M:%S%.3f"), sample.millis, sample.deviation, sample.offset, sample.angle())
+}
+
+fn quit_pressed() -> io::Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        let TermEvent::Key(KeyEvent {code, modifiers, kind, ..}) = event::read()? else {continue};
+        if kind == KeyEventKind::Release {continue}
+        match code {
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(true),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(true),
+            _ => ()
+        }
+    }
+    Ok(false)
+}
+
+struct Graph<'a> {
+    port: &'a str,
+    tuning: Tuning,
+    samples: VecDeque<Sample>
+}
+
+impl Graph<'_> {
+    //  COM4  angle +12.3°  deviation +12.3°  offset +0.0°  threshold 65°, ...
+    // +75°|
+    // +65°|------------------------------------
+    //     |                 ****
+    //   0°|·······*****·····    ·····
+    // -65°|------------------------------------
+    // The range grows to fit what's on screen, but always shows the threshold.
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let rows = height.saturating_sub(2).max(3);
+        let cols = width.saturating_sub(6).max(1); // the labels take 5, and the axis 1
+        while self.samples.len() > cols {self.samples.pop_front();}
+
+        let threshold = self.tuning.threshold as f32;
+        let range = self.samples.iter().map(|s| s.angle().abs()).fold(threshold + 10.0, f32::max).min(360.0);
+        let row_of = |deg: f32| (((range - deg) / (2.0 * range)) * (rows - 1) as f32).round().clamp(0.0, (rows - 1) as f32) as usize;
+
+        let mut grid = vec![vec![' '; cols]; rows];
+        let mut labels = vec![String::new(); rows];
+        let rearm = threshold - self.tuning.hysteresis as f32;
+        for (deg, c) in [(0.0, '·'), (rearm, '.'), (-rearm, '.'), (threshold, '-'), (-threshold, '-')] {
+            let row = row_of(deg);
+            grid[row].fill(c);
+            labels[row] = format!("{deg:+.0}°");
+        }
+        labels[0] = format!("{range:+.0}°");
+        labels[rows - 1] = format!("{:+.0}°", -range);
+
+        let offset = cols - self.samples.len();
+        for (i, sample) in self.samples.iter().enumerate() {
+            grid[row_of(sample.angle())][offset + i] = '*';
+        }
+
+        queue!(out, cursor::MoveTo(0, 0), terminal::Clear(ClearType::CurrentLine))?;
+        let header = match self.samples.back() {
+            Some(s) => format!(" {}  angle {:+.1}°  deviation {:+.1}°  offset {:+.1}°  {}", self.port, s.angle(), s.deviation, s.offset, self.tuning),
+            None => format!(" {}  waiting for samples...", self.port)
+        };
+        queue!(out, Print(header.chars().take(width).collect::<String>()))?;
+
+        for (row, (line, label)) in grid.iter().zip(&labels).enumerate() {
+            queue!(out, cursor::MoveTo(0, row as u16 + 1), Print(format!("{label:>5}|")))?;
+            for &c in line {
+                match c {
+                    '*' => queue!(out, SetForegroundColor(Color::Cyan), Print(c), ResetColor)?,
+                    '-' => queue!(out, SetForegroundColor(Color::Red), Print(c), ResetColor)?,
+                    _ => queue!(out, SetForegroundColor(Color::DarkGrey), Print(c), ResetColor)?
+                }
+            }
+        }
+        queue!(out, cursor::MoveTo(0, rows as u16 + 1), terminal::Clear(ClearType::CurrentLine), Print(" q quits"))?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows() {
+        let time = NaiveDateTime::parse_from_str("2026-10-18 12:00:00.125", "%Y-%m-%d %H:%M:%S%.3f").unwrap();
+        let sample = Sample {millis: 1500, deviation: 100.5, offset: -90.0};
+        let row = csv_row(time, &sample);
+        assert_eq!(row, "2026-10-18 12:00:00.125,1500,100.5,-90,-169.5");
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+    }
+}
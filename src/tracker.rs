@@ -0,0 +1,312 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+use crate::{orientation::Orientation, protocol::{Tuning, Reading}, config::Filter};
+
+// What the firmware does with the IMU's readings, for the simulator and for deciding on the host.
+// The angle is how far the monitor is tilted from where the current orientation is upright (the deviation minus the
+// offset). Once it's been past the threshold for the debounce, the monitor turns a quarter that way (half past
+// threshold + 90°), and it has to come back within threshold - hysteresis of the new orientation before it turns again.
+#[derive(Clone, Debug)]
+pub struct Tracker {
+    pub orientation: Orientation,
+    pub offset: f32, // the deviation at which the current orientation is upright
+    pub tuning: Tuning,
+    armed: bool, // false after turning, until the angle comes back
+    past_since: Option<Instant> // when the angle went past the threshold
+}
+
+impl Tracker {
+    pub fn new(orientation: Orientation, offset: f32, tuning: Tuning) -> Self {
+        Tracker {orientation, offset: wrap(offset), tuning, armed: true, past_since: None}
+    }
+
+    // Starts over from `orientation`, like the firmware does on DC1. The offset stays.
+    pub fn reset(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        self.armed = true;
+        self.past_since = None;
+    }
+
+    pub fn is_armed(&self) -> bool {self.armed}
+
+    pub fn angle(&self, deviation: f32) -> f32 {wrap(deviation - self.offset)}
+
+    // The new orientation if this reading turned the monitor.
+    pub fn update(&mut self, deviation: f32, now: Instant) -> Option<Orientation> {
+        let angle = self.angle(deviation);
+        if !self.armed {
+            if angle.abs() < self.tuning.threshold as f32 - self.tuning.hysteresis as f32 {self.armed = true}
+            return None;
+        }
+
+        let turns = turns(angle, self.tuning.threshold);
+        if turns == 0 {
+            self.past_since = None;
+            return None;
+        }
+        let since = *self.past_since.get_or_insert(now);
+        if now.saturating_duration_since(since) < Duration::from_millis(self.tuning.debounce_ms as u64) {return None}
+
+        self.past_since = None;
+        self.armed = false;
+        self.orientation = self.orientation.turned(-turns);
+        self.offset = wrap(self.offset + 90.0 * turns as f32);
+        Some(self.orientation)
+    }
+}
+
+// For [autorotate.host]: the firmware streams what the accelerometer reads, and this does what it would've done with it.
+// The readings get smoothed by `filter` first, and once that's settled the monitor is taken to be upright in the
+// orientation it started out in.
+pub struct Decider {
+    pub tracker: Tracker,
+    filter: Filter,
+    window: VecDeque<(f32, f32)>,      // Average: the last few readings' x and y
+    smoothed: Option<(u32, f32, f32)>, // LowPass: the last reading's millis, and x and y so far
+    since: Option<u32>,                // millis of the first reading since (re)calibrating
+    calibrated: bool
+}
+
+impl Decider {
+    pub fn new(orientation: Orientation, tuning: Tuning, filter: Filter) -> Self {
+        Decider {tracker: Tracker::new(orientation, 0.0, tuning), filter, window: VecDeque::new(), smoothed: None, since: None, calibrated: false}
+    }
+
+    // Like DC3: wherever it's pointing once the filter settles again is upright.
+    pub fn recalibrate(&mut self) {
+        self.since = None;
+        self.calibrated = false;
+    }
+
+    pub fn is_calibrated(&self) -> bool {self.calibrated}
+
+    // The new orientation if this reading turned the monitor.
+    pub fn update(&mut self, reading: &Reading, now: Instant) -> Option<Orientation> {
+        let deviation = self.smooth(reading);
+        if self.calibrated {return self.tracker.update(deviation, now)}
+
+        let since = *self.since.get_or_insert(reading.millis);
+        let settled = match self.filter {
+            Filter::None => true,
+            Filter::Average {samples} => self.window.len() >= samples as usize,
+            Filter::LowPass {time_constant_ms} => reading.millis.wrapping_sub(since) >= time_constant_ms as u32
+        };
+        if settled {
+            self.tracker.offset = deviation;
+            self.tracker.reset(self.tracker.orientation);
+            self.calibrated = true;
+        }
+        None
+    }
+
+    fn smooth(&mut self, reading: &Reading) -> f32 {
+        let (x, y) = match self.filter {
+            Filter::None => (reading.x, reading.y),
+            Filter::Average {samples} => {
+                self.window.push_back((reading.x, reading.y));
+                while self.window.len() > samples as usize {self.window.pop_front();}
+                let (x, y) = self.window.iter().fold((0.0, 0.0), |(x, y), &(wx, wy)| (x + wx, y + wy));
+                (x / self.window.len() as f32, y / self.window.len() as f32)
+            }
+            Filter::LowPass {time_constant_ms} => {
+                let (x, y) = match self.smoothed {
+                    Some((millis, x, y)) if time_constant_ms > 0 => {
+                        let dt = reading.millis.wrapping_sub(millis) as f32;
+                        let alpha = dt / (dt + time_constant_ms as f32);
+                        (x + alpha * (reading.x - x), y + alpha * (reading.y - y))
+                    }
+                    _ => (reading.x, reading.y)
+                };
+                self.smoothed = Some((reading.millis, x, y));
+                (x, y)
+            }
+        };
+        deviation(x, y)
+    }
+}
+
+// The firmware's deviation(): which way the accelerometer says down is, in (-180°, 180°].
+pub fn deviation(x: f32, y: f32) -> f32 {
+    wrap(x.atan2(y).to_degrees())
+}
+
+// How many quarter turns the monitor should make when it's tilted `angle` past upright, negative when the angle is.
+// Both ways are the same apart from the sign, and NaN (a reading of 0/0) never turns.
+pub fn turns(angle: f32, threshold: u8) -> i8 {
+    let angle = wrap(angle);
+    let threshold = threshold as f32;
+    let turns = if angle.is_nan() || angle.abs() <= threshold {0} else if angle.abs() >= threshold + 90.0 {2} else {1};
+    if angle < 0.0 {-turns} else {turns}
+}
+
+// into (-180°, 180°], deviation() can be anywhere in there and offsets add up
+pub fn wrap(deg: f32) -> f32 {
+    if deg > -180.0 && deg <= 180.0 {return deg} // rem_euclid() would round it
+    let deg = deg.rem_euclid(360.0);
+    if deg > 180.0 {deg - 360.0} else {deg}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // -720° to 720° in steps that don't land on round numbers
+    fn angles() -> impl Iterator<Item = f32> {
+        (0..=3900).map(|i| -720.0 + i as f32 * 0.37)
+    }
+
+    fn tuning(threshold: u8, hysteresis: u8, debounce_ms: u16) -> Tuning {
+        Tuning {threshold, hysteresis, debounce_ms}
+    }
+
+    // monitor-autorotate.ino's rotate(), from before update_orientation() calls it: the new orientation & offset
+    fn firmware_rotate(ori: Orientation, offset: f32, deviation: f32, threshold: u8) -> (Orientation, f32) {
+        let fw_wrap = |deg: f32| {
+            let deg = deg % 360.0; // fmod()
+            if deg > 180.0 {deg - 360.0} else if deg <= -180.0 {deg + 360.0} else {deg}
+        };
+        let angle = fw_wrap(deviation - offset);
+        let (threshold, limit) = (threshold as f32, threshold as f32 + 90.0);
+        let (ori, offset) = if angle.abs() <= threshold {
+            (ori as u8, offset)
+        } else if angle > threshold {
+            if angle >= limit {((ori as u8 + 2)%4, offset + 180.0)} else {((ori as u8 + 3)%4, offset + 90.0)}
+        } else if -angle >= limit {
+            ((ori as u8 + 2)%4, offset - 180.0)
+        } else {
+            ((ori as u8 + 1)%4, offset - 90.0)
+        };
+        (Orientation::from_u8(ori).unwrap(), fw_wrap(offset))
+    }
+
+    #[test]
+    fn turns_is_symmetric() {
+        for threshold in 1..90 {
+            for a in angles() {
+                if wrap(a) == 180.0 {continue} // -180° is 180°, there's no other way round
+                assert_eq!(turns(-a, threshold), -turns(a, threshold), "angle {a}, threshold {threshold}");
+            }
+        }
+        assert_eq!(turns(f32::NAN, 65), 0);
+    }
+
+    #[test]
+    fn wrap_stays_in_range() {
+        let edges = [-540.0, -360.0, -180.0, -180.00002, -179.99998, 0.0, -0.0, 180.0, 180.00002, 360.0, 540.0, 1e9, -1e9];
+        for deg in angles().chain(edges) {
+            let w = wrap(deg);
+            assert!(w > -180.0 && w <= 180.0, "wrap({deg}) = {w}");
+        }
+        assert_eq!(wrap(-180.0), 180.0);
+        assert_eq!(wrap(12.5), 12.5); // in range stays exactly as it is
+    }
+
+    #[test]
+    fn turns_like_the_firmware() {
+        let now = Instant::now();
+        for threshold in (1..90).step_by(4) {
+            for offset in [-170.0, -90.0, -12.3, 0.0, 45.0, 179.0] {
+                for ori in Orientation::ALL {
+                    for a in angles().step_by(3) {
+                        let deviation = wrap(offset + a);
+                        let mut tracker = Tracker::new(ori, offset, tuning(threshold, 0, 0));
+                        let turned = tracker.update(deviation, now);
+
+                        let (fw_ori, fw_offset) = firmware_rotate(ori, offset, deviation, threshold);
+                        assert_eq!(tracker.orientation, fw_ori, "threshold {threshold}, offset {offset}, deviation {deviation}");
+                        assert!(wrap(tracker.offset - fw_offset).abs() < 1e-3, "threshold {threshold}, offset {offset}, deviation {deviation}: {} vs {fw_offset}", tracker.offset);
+                        assert_eq!(turned, (fw_ori != ori).then_some(fw_ori)); // every turn changes it
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flips_both_ways() {
+        let now = Instant::now();
+        for deviation in [160.0, -160.0] {
+            let mut tracker = Tracker::new(Orientation::Landscape, 0.0, tuning(65, 5, 0));
+            assert_eq!(tracker.update(deviation, now), Some(Orientation::LandscapeFlipped), "deviation {deviation}");
+            assert_eq!(tracker.offset, 180.0);
+            assert_eq!(firmware_rotate(Orientation::Landscape, 0.0, deviation, 65), (Orientation::LandscapeFlipped, 180.0));
+        }
+    }
+
+    #[test]
+    fn rearms_below_threshold_minus_hysteresis() {
+        let now = Instant::now();
+        for threshold in [30, 45, 65, 85] {
+            for hysteresis in [0, 5, 10, 20] {
+                if hysteresis >= threshold {continue}
+                for sign in [1.0, -1.0] {
+                    let mut tracker = Tracker::new(Orientation::Landscape, 0.0, tuning(threshold, hysteresis, 0));
+                    assert!(tracker.update(sign * (threshold as f32 + 1.0), now).is_some());
+                    assert!(!tracker.is_armed());
+
+                    // coming back from way past the threshold of the new orientation
+                    let rearm = threshold as f32 - hysteresis as f32;
+                    for i in 0..=(threshold as u32 + 80) * 2 { // down to upright
+                        let a = (threshold as f32 + 80.0) - i as f32 * 0.5;
+                        let deviation = tracker.offset + sign * a;
+                        let was_armed = tracker.is_armed();
+                        let turned = tracker.update(deviation, now);
+                        if !was_armed {assert_eq!(turned, None, "threshold {threshold}, hysteresis {hysteresis}, angle {a}")}
+                        assert_eq!(tracker.is_armed(), a < rearm, "threshold {threshold}, hysteresis {hysteresis}, angle {a}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decider_debounces() {
+        let reading = |millis: u32, deg: f32| {
+            let (x, y) = deg.to_radians().sin_cos();
+            Reading {millis, x, y, z: 0.0}
+        };
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut decider = Decider::new(Orientation::Landscape, tuning(65, 5, 200), Filter::None);
+
+        // no filter, so the first reading is upright
+        assert_eq!(decider.update(&reading(0, 10.0), at(0)), None);
+        assert!(decider.is_calibrated());
+        assert!((decider.tracker.offset - 10.0).abs() < 1e-4);
+
+        assert_eq!(decider.update(&reading(100, 90.0), at(100)), None);
+        assert_eq!(decider.update(&reading(299, 90.0), at(299)), None);
+        // back within the threshold starts it over
+        assert_eq!(decider.update(&reading(300, 20.0), at(300)), None);
+        assert_eq!(decider.update(&reading(400, 90.0), at(400)), None);
+        assert_eq!(decider.update(&reading(599, 90.0), at(599)), None);
+        assert_eq!(decider.update(&reading(600, 90.0), at(600)), Some(Orientation::PortraitFlipped));
+        // not again until it's re-armed
+        assert_eq!(decider.update(&reading(900, 90.0 + 90.0), at(900)), None);
+    }
+
+    #[test]
+    fn filters_ignore_noise() {
+        let start = Instant::now();
+        // every reading 20ms apart and ±10° off, every 5th one a bump 50° further
+        let noisy = |i: u32, deg: f32| {
+            let deg = deg + 10.0 * (i as f32 * 2.3).sin() + if i % 5 == 4 {50.0} else {0.0};
+            let (x, y) = deg.to_radians().sin_cos();
+            (Reading {millis: i * 20, x, y, z: 0.0}, start + Duration::from_millis(i as u64 * 20))
+        };
+        for filter in [Filter::Average {samples: 10}, Filter::LowPass {time_constant_ms: 200}, Filter::None] {
+            let mut decider = Decider::new(Orientation::Landscape, tuning(65, 5, 0), filter);
+            let turned = (0..100).map(|i| noisy(i, if i < 25 {0.0} else {40.0})).find_map(|(reading, now)| decider.update(&reading, now));
+            assert!(decider.is_calibrated());
+            if filter == Filter::None {
+                assert!(turned.is_some()); // the bumps alone would do it
+                continue;
+            }
+            assert_eq!(turned, None, "{filter:?}");
+            assert!(decider.tracker.offset.abs() < 10.0, "{filter:?}");
+
+            // actually turned
+            let turned = (100..150).map(|i| noisy(i, 110.0)).find_map(|(reading, now)| decider.update(&reading, now));
+            assert_eq!(turned, Some(Orientation::PortraitFlipped), "{filter:?}");
+        }
+    }
+}
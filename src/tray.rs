@@ -0,0 +1,547 @@
+use std::{io::{self, Write, Read}, sync::{Arc, Mutex}, thread, time::Duration, path::{Path, PathBuf}};
+use chrono::Local;
+use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
+use winit::{event::Event, event_loop::{ControlFlow, EventLoop}};
+use serialport::SerialPortInfo;
+use trayicon::{MenuBuilder, TrayIconBuilder, TrayIcon};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use everythingdoer::{orientation::Orientation, protocol::{Request, Response, Tuning}, display, autorotate::{Device, UsbId}, config::{Config, SerialConfig, Parity, FlowControl}, lock::Lock, error, info, debug};
+use crate::{open_device, reopen_device, spawn_listener, set_autorotate, set_tuning, watch_config, reload_config, apply_config, CONNECTION_CHANGED,
+    current_lock, set_lock, toggle_lock, watch_lock, spawn_hotkey,
+    print_ports, test_comms, query_status, print_firmware, tune, recalibrate, rotate_monitor, calc_factorial, write_factorial, print_factorial, serial_send, serial_success, serial_error};
+
+/* #region ENUMS */
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Events {
+    //CudaFactorial,
+    Factorial,
+    SerialEnum,
+    // usize = index into config.autorotate.bindings
+    SerialTestComms(usize),
+    SerialQueryStatus(usize), SerialIMURecalibrate(usize), SerialFirmware(usize),
+    SerialAutoRotateMonitor(usize),
+    SerialRotateMonitor(usize, Orientation),
+    SerialPortChanged(usize, usize),
+    SerialBaudRate(usize, u32), SerialParity(usize, Parity), SerialStopBits(usize, u8), SerialFlowControl(usize, FlowControl),
+    SerialDtr(usize), SerialRts(usize),
+    SerialTuning(usize), SerialThreshold(usize, u8), SerialHysteresis(usize, u8), SerialDebounce(usize, u16),
+    // u32 = minutes
+    LockToggle, LockFor(u32), LockChanged,
+
+    HideConsole, RefreshMenu, ReloadConfig, ConnectionChanged,
+    Exit//, None
+}
+
+/* #endregion */
+
+// the ones in the menu, the config can set any
+const BAUD_RATES: [u32; 8] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+const THRESHOLDS: [u8; 6] = [30, 45, 55, 65, 75, 85];
+const HYSTERESES: [u8; 5] = [0, 5, 10, 15, 20];
+const DEBOUNCES: [u16; 5] = [0, 100, 250, 500, 1000];
+const LOCK_MINUTES: [u32; 4] = [5, 15, 30, 60];
+
+pub fn run(mut config: Config, config_path: PathBuf) -> ! {
+    /*clr_print!(stdout, (Magenta, true), "<——————————————————————————————————————————————————————————————————————————————————————>");
+    clr_print!(stdout, (Cyan, true), r"
+    ______                      __  __    _                 __                "); clr_print!(stdout, (Magenta, true), "███ ██ ██"); clr_print!(stdout, (Cyan, true), r"
+   / ____/   _____  _______  __/ /_/ /_  (_)___  ____ _____/ /___  ___  _____ "); clr_print!(stdout, (Magenta, true), " █  █ █ █"); clr_print!(stdout, (Cyan, true), r"
+  / __/ | | / / _ \/ ___/ / / / __/ __ \/ / __ \/ __ `/ __  / __ \/ _ \/ ___/          
+ / /___ | |/ /  __/ /  / /_/ / /_/ / / / / / / / /_/ / /_/ / /_/ /  __/ /              
+/_____/ |___/\___/_/   \__, /\__/_/ /_/_/_/ /_/\__, /\__,_/\____/\___/_/               
+                      /____/                  /____/                                   
+");// */
+    rule();
+
+    let event_loop = EventLoop::with_user_event();
+    let lock_path = Lock::path(&config_path);
+    let icon = include_bytes!("icon.ico");
+
+    let display = Arc::new(Mutex::new(display::native().unwrap()));
+    let mut devices: Vec<Arc<Mutex<Device>>> = config.autorotate.bindings.iter().map(|binding| {
+        Arc::new(Mutex::new(open_device(binding, &config, &display)))
+    }).collect();
+
+    /* #region TASKBAR MENU SETUP */
+    // what the port items in the menu were built from, SerialPortChanged indexes into it
+    let mut menu_ports: Vec<SerialPortInfo> = Vec::new();
+    macro_rules! menu {
+        () => {MenuBuilder::new()
+            /*.with(MenuItem::Item {
+                id: Events::None,
+                name: "Everythingdoer™".to_string(),
+                disabled: true,
+                icon: Some(Icon::from_buffer(icon, None, None).unwrap())
+            })*/
+
+            .separator()
+
+            .item("Refresh menu", Events::RefreshMenu)
+            .checkable("Hide console", false, Events::HideConsole)
+
+            .separator()
+
+            .submenu(&format!("Orientation lock ({})", current_lock()), lock_menu(current_lock()))
+            .submenu("Serial (Arduino)", {
+                menu_ports = serialport::available_ports().unwrap_or_default();
+                let mut ret = MenuBuilder::new()
+                    .item("Print serial ports", Events::SerialEnum)
+                    .separator();
+
+                for (i, device) in devices.iter().enumerate() {
+                    let mut dev = device.lock().unwrap();
+                    dev.current_ori = display.lock().unwrap().orientation(dev.binding.display).unwrap_or(dev.current_ori);
+                    ret = ret.submenu(&format!("Monitor {} ({})", dev.binding.display, dev.port), device_menu(i, &dev, &menu_ports));
+                }
+                ret
+            })
+            .item("Factorial calc", Events::Factorial)
+
+            .separator()
+
+            .item("Exit", Events::Exit)
+        }
+    }
+
+    let tray_icon = Arc::new(Mutex::new(TrayIconBuilder::new()
+        .sender_winit(unsafe {std::mem::transmute(event_loop.create_proxy())}) //it is literally the same struct shut up compiler
+        .icon_from_buffer(icon)
+        .tooltip(&tooltip(&devices))
+        .on_click(Events::HideConsole)
+        .on_double_click(Events::RefreshMenu)
+        .menu(menu!()).build().unwrap()));
+    /* #endregion */
+
+    /* #region SERIAL LISTENER THREADS */
+    for device in &devices {
+        spawn_listener(device, &display);
+    }
+    let proxy_t = event_loop.create_proxy();
+    *CONNECTION_CHANGED.lock().unwrap() = Some(Box::new(move || {_=proxy_t.send_event(Events::ConnectionChanged);}));
+    /* #endregion */
+
+    /* #region CONFIG WATCHER THREAD */
+    let proxy_t = event_loop.create_proxy();
+    let config_path_t = config_path.clone();
+    thread::spawn(move || watch_config(&config_path_t, || proxy_t.send_event(Events::ReloadConfig).is_ok()));
+    /* #endregion */
+
+    /* #region ORIENTATION LOCK THREADS */
+    let proxy_t = event_loop.create_proxy();
+    let lock_path_t = lock_path.clone();
+    thread::spawn(move || watch_lock(&lock_path_t, || proxy_t.send_event(Events::LockChanged).is_ok()));
+
+    // a new hotkey in the config only gets registered after a restart
+    if let Some(hotkey) = config.autorotate.lock_hotkey.as_ref().and_then(|h| h.parse().ok()) {
+        let proxy_t = event_loop.create_proxy();
+        spawn_hotkey(hotkey, move || proxy_t.send_event(Events::LockToggle).is_ok());
+    }
+    /* #endregion */
+
+    /* #region NEW WINDOW HANDLER */
+    /*unsafe {
+        extern "system" fn hookproc() {0}
+        let hhook = SetWindowsHookExA(WINDOWS_HOOK_ID(10), Some(hookproc), 0, );
+    }*/
+    /* #endregion */
+
+    /* #region STARTUP EVENTS THREAD */
+    let proxy_t = event_loop.create_proxy();
+    let binding_count = devices.len();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_secs(1));
+        if (0..binding_count).all(|i| proxy_t.send_event(Events::SerialAutoRotateMonitor(i)).is_ok()) {
+            thread::sleep(Duration::from_secs(1));
+            _=proxy_t.send_event(Events::HideConsole);
+        }
+    });
+    /* #endregion */
+
+    //MAIN LOOP
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+
+        if let Event::UserEvent(e) = event {
+            debug!(Tray, "`Events::{e:?}`");
+
+            match e {
+                Events::Exit => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    for (i, device) in devices.iter().enumerate() {
+                        let mut dev = device.lock().unwrap();
+                        if let Some(ref mut client) = dev.client {
+                            console_to_fg(&mut tray_lock);
+                            match serial_send(Request::Stop, || client.stop()) {
+                                Some(true) => {
+                                    serial_success(Response::Ack, "");
+                                    dev.autorotate = false;
+                                    _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor(i), false);
+                                }
+                                Some(false) => serial_error(Response::Nak),
+                                None => ()
+                            }
+                        }
+                    }
+                    drop(tray_lock);
+                    rule();
+
+                    console_to_fg(&mut tray_icon.lock().unwrap());
+                    *control_flow = ControlFlow::Exit;
+                }
+                Events::HideConsole => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    if let Some(oldv) = tray_lock.get_menu_item_checkable(Events::HideConsole) {
+                        _=tray_lock.set_menu_item_checkable(Events::HideConsole, !oldv);
+
+                        if oldv {winconsole::window::show(true);}
+                        else    {winconsole::window::hide();}
+                    }
+                }
+                Events::RefreshMenu => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    if let Err(e) = tray_lock.set_menu(&menu!()) {
+                        console_to_fg(&mut tray_lock);
+                        error!(Tray, "Couldn't refresh the menu: {e}");
+                    }
+                }
+                Events::ReloadConfig => if let Some(new) = reload_config(&config_path, &config) {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    apply_config(&mut devices, &display, &new);
+                    config = new;
+
+                    if let Err(e) = tray_lock.set_menu(&menu!()) {
+                        console_to_fg(&mut tray_lock);
+                        error!(Tray, "Couldn't refresh the menu: {e}");
+                    }
+                    _=tray_lock.set_tooltip(&tooltip(&devices));
+                }
+                // the firmware & autorotation state in the menu might've changed too
+                Events::ConnectionChanged => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    _=tray_lock.set_tooltip(&tooltip(&devices));
+                    _=tray_lock.set_menu(&menu!());
+                }
+
+                Events::LockToggle | Events::LockFor(_) | Events::LockChanged => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let ok = match e {
+                        Events::LockToggle => toggle_lock(&lock_path),
+                        Events::LockFor(minutes) => set_lock(&lock_path, Lock::Until(Local::now() + chrono::Duration::minutes(minutes as i64))),
+                        _ => true // watch_lock() already logged it
+                    };
+                    if !ok {console_to_fg(&mut tray_lock);}
+                    _=tray_lock.set_tooltip(&tooltip(&devices));
+                    _=tray_lock.set_menu(&menu!());
+                }
+
+                Events::SerialRotateMonitor(i, ori) => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let mut dev = devices[i].lock().unwrap();
+                    let dev = &mut *dev;
+                    if rotate_monitor(&mut **display.lock().unwrap(), dev.binding.display, ori, dev.binding.profiles.get(ori), &mut dev.current_ori) {
+                        for o in Orientation::ALL {
+                            _=tray_lock.set_menu_item_checkable(Events::SerialRotateMonitor(i, o), o==ori);
+                        }
+                    }
+                }
+                Events::SerialAutoRotateMonitor(i) => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let mut dev = devices[i].lock().unwrap();
+                    if dev.client.is_some() {console_to_fg(&mut tray_lock);} // otherwise it's just remembered for when it reconnects
+                    let on = !dev.autorotate;
+                    if set_autorotate(&mut dev, on) {
+                        _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor(i), on);
+
+                        for o in Orientation::ALL {
+                            _=tray_lock.set_menu_item_checkable(Events::SerialRotateMonitor(i, o), !on && o==dev.current_ori);
+                        }
+                    }
+                }
+                Events::SerialEnum => {
+                    console_to_fg(&mut tray_icon.lock().unwrap());
+                    print_ports();
+                }
+                Events::SerialTestComms(i) => {
+                    if let Some(ref mut client) = devices[i].lock().unwrap().client {
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        test_comms(client);
+                    }
+                }
+                Events::SerialQueryStatus(i) => {
+                    if let Some(ref mut client) = devices[i].lock().unwrap().client {
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        query_status(client);
+                    }
+                }
+                Events::SerialFirmware(i) => {
+                    if let Some(ref client) = devices[i].lock().unwrap().client {
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        print_firmware(client);
+                    }
+                }
+                Events::SerialIMURecalibrate(i) => {
+                    let mut dev = devices[i].lock().unwrap();
+                    let dev = &mut *dev;
+                    if let Some(ref mut decider) = dev.decider { // the firmware's calibration isn't used
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        decider.recalibrate();
+                        info!(Serial, "`{}`: recalibrating, the next readings are upright.", dev.port);
+                    } else if let Some(ref mut client) = dev.client {
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        recalibrate(client);
+                    }
+                }
+                Events::SerialPortChanged(i, x) => if let Some(info) = menu_ports.get(x) {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let mut dev = devices[i].lock().unwrap();
+                    dev.binding.port = info.port_name.clone();
+                    dev.binding.usb = UsbId::of(info); //so reconnects find it again if it comes back as another port
+                    reopen_device(&mut dev, &info.port_name);
+                    _=tray_lock.set_menu_item_checkable(Events::SerialAutoRotateMonitor(i), dev.autorotate);
+
+                    let mut j = 0;
+                    while tray_lock.get_menu_item_checkable(Events::SerialPortChanged(i, j)).is_some() {
+                        _=tray_lock.set_menu_item_checkable(Events::SerialPortChanged(i, j), j==x);
+                        j+=1;
+                    }
+                }
+                Events::SerialBaudRate(i, _) | Events::SerialParity(i, _) | Events::SerialStopBits(i, _) | Events::SerialFlowControl(i, _) |
+                Events::SerialDtr(i) | Events::SerialRts(i) => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let mut dev = devices[i].lock().unwrap();
+                    match e {
+                        Events::SerialBaudRate(_, baud_rate) => dev.serial.baud_rate = baud_rate,
+                        Events::SerialParity(_, parity) => dev.serial.parity = parity,
+                        Events::SerialStopBits(_, stop_bits) => dev.serial.stop_bits = stop_bits,
+                        Events::SerialFlowControl(_, flow_control) => dev.serial.flow_control = flow_control,
+                        Events::SerialDtr(_) => dev.serial.dtr = !dev.serial.dtr,
+                        Events::SerialRts(_) => dev.serial.rts = Some(dev.serial.rts != Some(true)),
+                        _ => unreachable!()
+                    }
+                    // a disconnected device picks them up when it reconnects
+                    if dev.client.is_some() {
+                        console_to_fg(&mut tray_lock);
+                        info!(Serial, "Reopening `{}` with the new settings...", dev.port);
+                        let port = dev.port.clone();
+                        reopen_device(&mut dev, &port);
+                    }
+                    drop(dev);
+
+                    if let Err(e) = tray_lock.set_menu(&menu!()) {
+                        console_to_fg(&mut tray_lock);
+                        error!(Tray, "Couldn't refresh the menu: {e}");
+                    }
+                }
+                Events::SerialTuning(i) => {
+                    if let Some(ref mut client) = devices[i].lock().unwrap().client {
+                        console_to_fg(&mut tray_icon.lock().unwrap());
+                        tune(client, None, None, None);
+                    }
+                }
+                Events::SerialThreshold(i, _) | Events::SerialHysteresis(i, _) | Events::SerialDebounce(i, _) => {
+                    let mut tray_lock = tray_icon.lock().unwrap();
+                    let mut dev = devices[i].lock().unwrap();
+                    let mut tuning = dev.tuning;
+                    match e {
+                        Events::SerialThreshold(_, threshold) => tuning.threshold = threshold,
+                        Events::SerialHysteresis(_, hysteresis) => tuning.hysteresis = hysteresis,
+                        Events::SerialDebounce(_, debounce_ms) => tuning.debounce_ms = debounce_ms,
+                        _ => unreachable!()
+                    }
+                    match tuning.validate() {
+                        Ok(()) => {
+                            if dev.client.is_some() && dev.autorotate {console_to_fg(&mut tray_lock);} // otherwise it's sent when autorotation starts
+                            set_tuning(&mut dev, tuning);
+                        }
+                        Err(e) => {
+                            console_to_fg(&mut tray_lock);
+                            error!(Serial, "{e}");
+                        }
+                    }
+                    drop(dev);
+
+                    if let Err(e) = tray_lock.set_menu(&menu!()) {
+                        console_to_fg(&mut tray_lock);
+                        error!(Tray, "Couldn't refresh the menu: {e}");
+                    }
+                }
+
+                Events::Factorial => {
+                    let hidden = console_to_fg(&mut tray_icon.lock().unwrap());
+                    prompt("Input number to calculate the factorial of: ");
+                    let n = input(|buffer| {
+                        if let Ok(n) = buffer.trim().parse::<u64>() {Some(n)} else {None}
+                    });
+                    let (reslv, resl_sn1, resl_sn2, calc_time) = calc_factorial(n, config.factorial_thread_count);
+
+                    let mut buffer = [0u8];
+                    prompt("Save to file (space) or display (any)? ");
+                    enable_raw_mode().unwrap();
+                    io::stdin().read(&mut buffer).unwrap();
+                    disable_raw_mode().unwrap();
+                    println!();
+
+                    if buffer[0] == b' ' {
+                        let path = format!(r"C:\Users\Roman\Desktop\everythingdoer\src\factorial\{}.txt", n);
+                        match write_factorial(Path::new(&path), &reslv, &resl_sn1, &resl_sn2, calc_time) {
+                            Ok(()) => info!(Factorial, "Wrote the result to `{path}`."),
+                            Err(e) => error!(Factorial, "Couldn't write to `{path}`: {e}")
+                        }
+
+                        _=open::that(path);
+                    } else {
+                        print_factorial(&reslv, &resl_sn1, &resl_sn2, calc_time);
+                    }
+
+                    if hidden {winconsole::window::hide();}
+                }
+                /*Events::CudaFactorial => {
+                    let path = format!(r"{}\src\external\CudaFactorial\x64\Release\CudaFactorial.exe", env!("CARGO_MANIFEST_DIR")); //don't ever do this. ever.
+                    _=std::process::Command::new(path).spawn().unwrap();
+                }*/
+
+                //_ => ()
+            }
+        }
+    });
+}
+
+fn device_menu(i: usize, dev: &Device, ports: &[SerialPortInfo]) -> MenuBuilder<Events> {
+    let manual = |o: Orientation| !dev.autorotate && dev.current_ori == o;
+    let firmware = match dev.client {
+        Some(ref client) => match client.firmware() {
+            Some(fw) => fw.to_string(),
+            None => format!("unknown ({})", client.mode())
+        }
+        None => "not connected".to_string()
+    };
+    let mut ret = MenuBuilder::new()
+        .item(&format!("Firmware: {firmware}"), Events::SerialFirmware(i))
+        .item("Test comms", Events::SerialTestComms(i))
+        .item("Query status", Events::SerialQueryStatus(i))
+        .item("Recalibrate IMU", Events::SerialIMURecalibrate(i))
+        .submenu("Monitor", MenuBuilder::new()
+            .checkable("Landscape",           manual(Orientation::Landscape),        Events::SerialRotateMonitor(i, Orientation::Landscape))
+            .checkable("Landscape (flipped)", manual(Orientation::LandscapeFlipped), Events::SerialRotateMonitor(i, Orientation::LandscapeFlipped))
+            .checkable("Portrait",            manual(Orientation::Portrait),         Events::SerialRotateMonitor(i, Orientation::Portrait))
+            .checkable("Portrait (flipped)",  manual(Orientation::PortraitFlipped),  Events::SerialRotateMonitor(i, Orientation::PortraitFlipped))
+            .checkable("Auto-rotate", dev.autorotate, Events::SerialAutoRotateMonitor(i))
+        )
+        .submenu("Tuning", tuning_menu(i, dev.tuning))
+        .submenu("Serial settings", serial_menu(i, &dev.serial))
+        .separator();
+
+    for (j, port) in ports.iter().enumerate() {
+        ret = ret.checkable(&port.port_name, port.port_name == dev.port, Events::SerialPortChanged(i, j));
+    }
+    ret
+}
+
+// Picks get sent right away if autorotation is running (see set_tuning()), and last until the config changes.
+fn tuning_menu(i: usize, tuning: Tuning) -> MenuBuilder<Events> {
+    let (mut thresholds, mut hystereses, mut debounces) = (MenuBuilder::new(), MenuBuilder::new(), MenuBuilder::new());
+    for threshold in THRESHOLDS {
+        thresholds = thresholds.checkable(&format!("{threshold}°"), tuning.threshold == threshold, Events::SerialThreshold(i, threshold));
+    }
+    for hysteresis in HYSTERESES {
+        hystereses = hystereses.checkable(&format!("{hysteresis}°"), tuning.hysteresis == hysteresis, Events::SerialHysteresis(i, hysteresis));
+    }
+    for debounce_ms in DEBOUNCES {
+        debounces = debounces.checkable(&format!("{debounce_ms}ms"), tuning.debounce_ms == debounce_ms, Events::SerialDebounce(i, debounce_ms));
+    }
+    MenuBuilder::new()
+        .item("Read from device", Events::SerialTuning(i))
+        .separator()
+        .submenu(&format!("Threshold ({}°)", tuning.threshold), thresholds)
+        .submenu(&format!("Hysteresis ({}°)", tuning.hysteresis), hystereses)
+        .submenu(&format!("Debounce ({}ms)", tuning.debounce_ms), debounces)
+}
+
+// Only lasts until the config changes, like picking a port.
+fn serial_menu(i: usize, serial: &SerialConfig) -> MenuBuilder<Events> {
+    let mut baud_rates = MenuBuilder::new();
+    for baud_rate in BAUD_RATES {
+        baud_rates = baud_rates.checkable(&baud_rate.to_string(), serial.baud_rate == baud_rate, Events::SerialBaudRate(i, baud_rate));
+    }
+    MenuBuilder::new()
+        .submenu(&format!("Baud rate ({})", serial.baud_rate), baud_rates)
+        .submenu("Parity", MenuBuilder::new()
+            .checkable("None", serial.parity == Parity::None, Events::SerialParity(i, Parity::None))
+            .checkable("Odd",  serial.parity == Parity::Odd,  Events::SerialParity(i, Parity::Odd))
+            .checkable("Even", serial.parity == Parity::Even, Events::SerialParity(i, Parity::Even))
+        )
+        .submenu("Stop bits", MenuBuilder::new()
+            .checkable("1", serial.stop_bits == 1, Events::SerialStopBits(i, 1))
+            .checkable("2", serial.stop_bits == 2, Events::SerialStopBits(i, 2))
+        )
+        .submenu("Flow control", MenuBuilder::new()
+            .checkable("None",                serial.flow_control == FlowControl::None,     Events::SerialFlowControl(i, FlowControl::None))
+            .checkable("Software (XON/XOFF)", serial.flow_control == FlowControl::Software, Events::SerialFlowControl(i, FlowControl::Software))
+            .checkable("Hardware (RTS/CTS)",  serial.flow_control == FlowControl::Hardware, Events::SerialFlowControl(i, FlowControl::Hardware))
+        )
+        .checkable("DTR", serial.dtr, Events::SerialDtr(i))
+        .checkable("RTS", serial.rts == Some(true), Events::SerialRts(i))
+}
+
+// Pushes keep getting ACKed while it's locked, see Lock.
+fn lock_menu(lock: Lock) -> MenuBuilder<Events> {
+    let mut ret = MenuBuilder::new()
+        .checkable("Locked", lock != Lock::Off, Events::LockToggle)
+        .separator();
+    for minutes in LOCK_MINUTES {
+        ret = ret.item(&format!("Lock for {minutes} minutes"), Events::LockFor(minutes));
+    }
+    ret
+}
+
+// e.g. "Everythingdoer™\nMonitor 1 (COM4): connected"
+fn tooltip(devices: &[Arc<Mutex<Device>>]) -> String {
+    let mut ret = "Everythingdoer™".to_string();
+    let lock = current_lock();
+    if lock != Lock::Off {ret += &format!("\nOrientation {lock}")}
+    for device in devices {
+        let dev = device.lock().unwrap();
+        let state = if dev.is_connected() {"connected"} else {"disconnected, retrying"};
+        ret += &format!("\nMonitor {} ({}): {state}", dev.binding.display, dev.port);
+    }
+    ret
+}
+
+fn console_to_fg(tray_icon: &mut TrayIcon<Events>) -> bool {
+    let mut hidden_before = true;
+    if let Some(oldv) = tray_icon.get_menu_item_checkable(Events::HideConsole) {
+        if !oldv {
+            hidden_before = false;
+            winconsole::window::hide();
+        }
+    }
+    winconsole::window::show(true);
+    hidden_before
+}
+
+fn input<T, F>(mut condition: F) -> T where F: FnMut(&str) -> Option<T> {
+    loop {
+        let mut buffer = String::new();
+        io::stdin().read_line(&mut buffer).unwrap();
+
+        if let Some(v) = condition(&buffer) {break v}
+        prompt("Invalid value, try again: ");
+    }
+}
+
+// Prompts aren't log messages, they go straight to the console.
+fn prompt(text: &str) {
+    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+    _=stdout.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)).set_intense(true));
+    _=write!(stdout, "{text}");
+    _=stdout.reset();
+    _=stdout.flush();
+}
+
+fn rule() {
+    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+    _=stdout.set_color(ColorSpec::new().set_fg(Some(Color::Magenta)).set_intense(true));
+    _=writeln!(stdout, "<——————————————————————————————————————————————————————————————————————————————————————>");
+    _=stdout.reset();
+}
@@ -0,0 +1,79 @@
+// The client against the simulated firmware, over a pty like `simulator` uses.
+#![cfg(unix)]
+
+use std::{io::{self, Read, Write}, sync::{Arc, Mutex, mpsc}, thread, time::Duration};
+use serialport::{SerialPort, TTYPort};
+use everythingdoer::{simulator, orientation::Orientation, protocol::{Client, Mode, Frame, FrameDecoder, ACK}};
+
+// Keeps what the device reads, to see the pushes get ACKed
+struct Tap(TTYPort, Arc<Mutex<Vec<u8>>>);
+impl Read for Tap {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.lock().unwrap().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+impl Write for Tap {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {self.0.write(buf)}
+    fn flush(&mut self) -> io::Result<()> {self.0.flush()}
+}
+
+fn rotate(framed: bool) {
+    let (mut master, mut slave) = TTYPort::pair().expect("couldn't create pty");
+    slave.set_exclusive(false).unwrap();
+    master.set_timeout(Duration::from_millis(10)).unwrap();
+    slave.set_timeout(Duration::from_millis(100)).unwrap();
+
+    // steps the device until the tilts run out
+    let read = Arc::new(Mutex::new(Vec::new()));
+    let (tilt, tilts) = mpsc::channel();
+    let mut device = simulator::Device::new(Tap(master, Arc::clone(&read)));
+    let device = thread::spawn(move || loop {
+        match tilts.try_recv() {
+            Ok(deg) => device.tilt = deg,
+            Err(mpsc::TryRecvError::Empty) => (),
+            Err(mpsc::TryRecvError::Disconnected) => break
+        }
+        device.step().unwrap();
+    });
+
+    let input = slave.try_clone().unwrap();
+    let mut client = Client::new(slave, input, Duration::from_secs(1));
+    if framed {assert_eq!(client.negotiate().unwrap(), Mode::Framed)}
+    let pushes = client.take_pushes().unwrap();
+
+    assert!(client.start(Orientation::Landscape, 45).unwrap());
+    assert!(client.query().unwrap());
+    read.lock().unwrap().clear();
+    tilt.send(30.0).unwrap(); // not far enough
+    assert!(pushes.recv_timeout(Duration::from_millis(300)).is_err());
+
+    tilt.send(80.0).unwrap();
+    assert_eq!(pushes.recv_timeout(Duration::from_secs(1)), Ok(Orientation::PortraitFlipped));
+    // a push that didn't get ACKed gets resent every 100ms, and legacy resends can't be told apart
+    assert!(pushes.recv_timeout(Duration::from_millis(300)).is_err());
+    let read = read.lock().unwrap().clone();
+    if framed {
+        let mut decoder = FrameDecoder::default();
+        read.iter().for_each(|&b| decoder.push(b));
+        assert_eq!(decoder.pop(), Some(Frame {seq: 0, payload: vec![ACK]}), "{read:02x?}"); // the device's first push
+    } else {
+        assert_eq!(read, [ACK]);
+    }
+
+    assert!(client.stop().unwrap());
+    assert!(!client.query().unwrap());
+    drop(tilt); // before the pty closes
+    device.join().unwrap();
+}
+
+#[test]
+fn rotates_legacy() {
+    rotate(false);
+}
+
+#[test]
+fn rotates_framed() {
+    rotate(true);
+}
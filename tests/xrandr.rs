@@ -0,0 +1,43 @@
+// Needs an X server with RandR, so it only runs when asked to:
+//   xvfb-run -s "-screen 0 1920x1080x24" cargo test --test xrandr -- --ignored
+// It changes the real screen otherwise, and puts it back afterwards.
+#![cfg(target_os = "linux")]
+
+use x11rb::{connection::Connection, protocol::randr::ConnectionExt as _};
+use everythingdoer::{display::{DisplayBackend, XRandrBackend, Profile}, orientation::Orientation};
+
+// The first enabled output whose crtc can do every rotation, Xvfb's can't always
+fn rotatable(display: &XRandrBackend) -> Option<u32> {
+    let (conn, screen) = x11rb::connect(None).ok()?;
+    let res = conn.randr_get_screen_resources_current(conn.setup().roots[screen].root).ok()?.reply().ok()?;
+    (0..display.outputs().ok()?.len() as u32).find(|&id| {
+        let info = conn.randr_get_output_info(res.outputs[id as usize], res.config_timestamp).ok().and_then(|c| c.reply().ok());
+        let crtc = info.filter(|i| i.crtc != 0).and_then(|i| conn.randr_get_crtc_info(i.crtc, res.config_timestamp).ok()?.reply().ok());
+        crtc.is_some_and(|c| u16::from(c.rotations) & 0xf == 0xf)
+    })
+}
+
+#[test]
+#[ignore = "needs an X server, see the top of the file"]
+fn rotates_and_restores() {
+    assert!(std::env::var_os("DISPLAY").is_some(), "no $DISPLAY, run it under xvfb-run");
+    let mut display = XRandrBackend::new(None).expect("couldn't connect to X");
+    let id = rotatable(&display).expect("no output that can do every rotation");
+    let (ori, (w, h)) = (display.orientation(id).unwrap(), display.resolution(id).unwrap());
+    let landscape = matches!(ori, Orientation::Landscape | Orientation::LandscapeFlipped);
+
+    for to in Orientation::ALL {
+        display.set_orientation(id, to).unwrap();
+        assert_eq!(display.orientation(id).unwrap(), to);
+        let swapped = landscape != matches!(to, Orientation::Landscape | Orientation::LandscapeFlipped);
+        assert_eq!(display.resolution(id).unwrap(), if swapped {(h, w)} else {(w, h)}, "{to:?}");
+    }
+
+    // a mode the output doesn't have fails before anything changes
+    let profile = Profile {resolution: Some((12345, 12344)), ..Default::default()};
+    assert!(display.set_mode(id, Orientation::Landscape, &profile).is_err());
+    assert_eq!(display.orientation(id).unwrap(), Orientation::PortraitFlipped);
+
+    display.set_orientation(id, ori).unwrap();
+    assert_eq!((display.orientation(id).unwrap(), display.resolution(id).unwrap()), (ori, (w, h)));
+}